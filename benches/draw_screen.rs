@@ -0,0 +1,91 @@
+//! How long does the GUI hold the emulator's mutex to get a frame? The CPU
+//! thread is stuck waiting that whole time.
+//!
+//! (This is only the emulator's side. Once it has the frame, the GUI paints
+//! the lines that changed into the window, without holding the lock.)
+//!
+//! Run with `cargo bench`.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use apple_ii_emulator::{debugger_commands::Command, Emulator};
+
+/// Switch to hi-res, then keep scribbling on the screen.
+const PROGRAM: &[u8] = &[
+    0xad, 0x50, 0xc0, // lda $c050
+    0xad, 0x57, 0xc0, // lda $c057
+    0xfe, 0x00, 0x20, // inc $2000,x
+    0xe8, //             inx
+    0x4c, 0x06, 0x03, // jmp $0306
+];
+
+fn main() {
+    let mut emu = Emulator::new(PROGRAM, 0x300, 0x300, vec![]);
+
+    // Run long enough to get through the ROM's reset routine, and draw a few
+    // frames.
+    for _ in 0..300 {
+        emu.sim_1000_instrs();
+    }
+    let emu = Mutex::new(emu);
+
+    // While running, the frame has already been drawn (incrementally) by the
+    // time we ask for it, and we just get a handle to it.
+    let shared = time(1_000, &emu, |emu| {
+        emu.sim_1000_instrs();
+    });
+
+    // What `draw_screen` used to do with that frame: copy it, while still
+    // holding the lock.
+    let copied = time_with(
+        1_000,
+        &emu,
+        |emu| {
+            emu.sim_1000_instrs();
+        },
+        |frame| frame.to_vec(),
+    );
+
+    // While halted, the whole screen gets rendered from memory on every call.
+    // This is roughly what every frame used to cost, but it's today's
+    // renderer, so it doesn't include the old code's unscrambling of each
+    // page into nested `Vec`s.
+    emu.lock().unwrap().control(Command::Halt);
+    let rendered = time(100, &emu, |_| ());
+
+    println!("lock held for draw_screen, running (shared frame):       {shared:?}");
+    println!("lock held for draw_screen, running (copied frame):       {copied:?}");
+    println!("lock held for draw_screen, halted (render from memory):  {rendered:?}");
+}
+
+/// Average time the lock is held for a call to `draw_screen`, not counting
+/// the time spent in `setup`.
+fn time(iters: u32, emu: &Mutex<Emulator>, setup: impl FnMut(&mut Emulator)) -> Duration {
+    time_with(iters, emu, setup, |_| ())
+}
+
+/// The same, but also doing `then` with the frame before letting go of the
+/// lock.
+fn time_with<T>(
+    iters: u32,
+    emu: &Mutex<Emulator>,
+    mut setup: impl FnMut(&mut Emulator),
+    mut then: impl FnMut(&[u32]) -> T,
+) -> Duration {
+    let mut total = Duration::ZERO;
+    for _ in 0..iters {
+        setup(&mut emu.lock().unwrap());
+
+        let start = Instant::now();
+        {
+            let emu = emu.lock().unwrap();
+            let frame = emu.draw_screen();
+            std::hint::black_box(then(&frame));
+        }
+        total += start.elapsed();
+    }
+    total / iters
+}
@@ -1,16 +1,36 @@
 mod arith;
+mod cycles;
+pub mod disasm;
 pub mod flags;
 pub mod instr;
 pub mod operand;
+pub mod shadow_stack;
 
 use std::fmt;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use flags::{Flag, Flags};
 use instr::{Instr, Mode};
 use operand::Operand;
-
-use crate::memory::AddressSpace;
+use shadow_stack::ShadowStack;
+
+use crate::{memory::AddressSpace, save_state::Sections, symbols::SymbolTable};
+
+/// The clock rate, in Hz. (It's not exactly 1 MHz, since it's derived from
+/// the NTSC color carrier.)
+pub const CLOCK_HZ: u64 = 1_020_484;
+
+/// The registers, by name. (For the debugger.)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    Sp,
+    Pc,
+    /// The flags, as a byte.
+    P,
+}
 
 #[derive(Clone)]
 pub struct Cpu {
@@ -20,6 +40,7 @@ pub struct Cpu {
     a: u8,
     x: u8,
     y: u8,
+    shadow_stack: ShadowStack,
 }
 
 impl Cpu {
@@ -31,22 +52,91 @@ impl Cpu {
             a: 0,
             x: 0,
             y: 0,
+            shadow_stack: ShadowStack::default(),
         }
     }
 
+    /// What happens when the RESET line goes low: jump to the address in the
+    /// reset vector. (The stack pointer goes down by 3, since the 6502 goes
+    /// through the motions of an interrupt without actually writing
+    /// anything.)
+    pub fn reset(&mut self, mem: &mut AddressSpace) {
+        self.sp = self.sp.wrapping_sub(3);
+        self.flags.set(Flag::Interrupt);
+        self.pc = u16::from_le_bytes([mem.read(0xfffc), mem.read(0xfffd)]);
+        self.shadow_stack.clear();
+    }
+
     pub fn pc(&self) -> u16 {
         self.pc
     }
 
+    pub fn shadow_stack(&self) -> &ShadowStack {
+        &self.shadow_stack
+    }
+
+    pub fn register(&self, register: Register) -> u16 {
+        match register {
+            Register::A => self.a as u16,
+            Register::X => self.x as u16,
+            Register::Y => self.y as u16,
+            Register::Sp => self.sp as u16,
+            Register::Pc => self.pc,
+            Register::P => self.flags.bits as u16,
+        }
+    }
+
+    pub fn flag(&self, flag: Flag) -> bool {
+        self.flags.is_set(flag)
+    }
+
+    pub fn set_register(&mut self, register: Register, value: u16) -> Result<()> {
+        if register == Register::Pc {
+            self.pc = value;
+            return Ok(());
+        }
+
+        let value = u8::try_from(value)
+            .ok()
+            .with_context(|| format!("{register:?} is 8 bits; ${value:04x} doesn't fit"))?;
+        match register {
+            Register::A => self.a = value,
+            Register::X => self.x = value,
+            Register::Y => self.y = value,
+            Register::Sp => self.sp = value,
+            Register::P => self.flags.bits = value,
+            Register::Pc => unreachable!(),
+        }
+        Ok(())
+    }
+
+    pub fn set_flag(&mut self, flag: Flag, setting: bool) {
+        self.flags.assign(flag, setting);
+    }
+
     pub fn next_instr(&self, mem: &mut AddressSpace) -> Result<(Instr, Mode, Operand)> {
-        let (instr, mode) = instr::decode(mem.read(self.pc))?;
+        let (instr, mode) = instr::decode(mem.read_unwatched(self.pc))?;
         let arg = Operand::new(self, mem, mode);
         Ok((instr, mode, arg))
     }
 
-    pub fn step(&mut self, mem: &mut AddressSpace) {
+    /// Execute one instruction, and return how many clock cycles it took.
+    pub fn step(&mut self, mem: &mut AddressSpace) -> u8 {
         let (instr, mode, arg) = self.next_instr(mem).unwrap();
 
+        let mut cycles = cycles::base(instr, mode);
+        if cycles::has_page_penalty(instr, mode) {
+            let index = if mode == Mode::AbsoluteX {
+                self.x
+            } else {
+                self.y
+            };
+            let addr = arg.addr();
+            if addr.wrapping_sub(index as u16) >> 8 != addr >> 8 {
+                cycles += 1;
+            }
+        }
+
         let mut pc_set = false;
         match instr {
             Instr::Brk => panic!("brk at 0x{:04x}", self.pc),
@@ -65,7 +155,7 @@ impl Cpu {
                 self.a = self.flags.nz(v);
             }
             Instr::Php => {
-                let mut f = self.flags.clone();
+                let mut f = self.flags;
                 f.set(Flag::Break);
                 f.set(Flag::Reserved);
                 self.push(mem, f.bits);
@@ -145,6 +235,13 @@ impl Cpu {
             | Instr::Bne
             | Instr::Beq) => {
                 if would_branch(b, self.flags) {
+                    // One extra cycle for taking the branch, and another for
+                    // landing on a different page.
+                    cycles += 1;
+                    if self.pc.wrapping_add(2) >> 8 != arg.addr() >> 8 {
+                        cycles += 1;
+                    }
+
                     self.pc = arg.addr();
                     pc_set = true;
                 }
@@ -157,11 +254,15 @@ impl Cpu {
             Instr::Jsr => {
                 let return_addr_minus_one = self.pc.checked_add(2).unwrap();
                 self.push2(mem, return_addr_minus_one);
+                self.shadow_stack.jsr(self.pc, self.sp);
                 self.pc = arg.addr();
                 pc_set = true;
             }
             Instr::Rts => {
-                self.pc = self.pop2(mem).checked_add(1).unwrap();
+                let sp = self.sp;
+                let to = self.pop2(mem).checked_add(1).unwrap();
+                self.shadow_stack.rts(self.pc, sp, to);
+                self.pc = to;
                 pc_set = true;
             }
             Instr::Rti => {
@@ -175,6 +276,9 @@ impl Cpu {
         if !pc_set {
             self.pc = self.pc.checked_add(mode.instr_len()).unwrap();
         }
+        self.shadow_stack.unwind(self.sp);
+
+        cycles
     }
 
     fn adc(&mut self, arg1: u8, arg2: u8) {
@@ -238,6 +342,15 @@ impl Cpu {
         mem.read(0x0100 + self.sp as u16)
     }
 
+    /// Call a subroutine, as if we'd just run a JSR. (For the debugger.)
+    pub fn jsr(&mut self, mem: &mut AddressSpace, addr: u16) {
+        // JSR pushes the address of its own last byte, and RTS adds 1.
+        self.push2(mem, self.pc.wrapping_sub(1));
+        // (As if the JSR was just before here.)
+        self.shadow_stack.jsr(self.pc.wrapping_sub(3), self.sp);
+        self.pc = addr;
+    }
+
     fn push2(&mut self, mem: &mut AddressSpace, word: u16) {
         let [lo, hi] = u16::to_le_bytes(word);
 
@@ -266,6 +379,30 @@ impl Cpu {
     }
 }
 
+/// Save states.
+impl Cpu {
+    pub fn save_state(&self, sections: &mut Sections) {
+        let mut data = self.pc.to_le_bytes().to_vec();
+        data.extend([self.sp, self.flags.bits, self.a, self.x, self.y]);
+        sections.add(b"CPU ", data);
+        self.shadow_stack.save_state(sections);
+    }
+
+    pub fn load_state(&mut self, sections: &Sections) -> Result<()> {
+        let mut r = sections.get(b"CPU ")?;
+        *self = Self {
+            pc: r.u16()?,
+            sp: r.u8()?,
+            flags: Flags { bits: r.u8()? },
+            a: r.u8()?,
+            x: r.u8()?,
+            y: r.u8()?,
+            shadow_stack: ShadowStack::default(),
+        };
+        self.shadow_stack.load_state(sections)
+    }
+}
+
 impl fmt::Debug for Cpu {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "pc: ${:04x}", self.pc)?;
@@ -280,57 +417,12 @@ impl fmt::Debug for Cpu {
 }
 
 impl Cpu {
-    pub fn dbg_next_instr(&self, mem: &mut AddressSpace) -> impl fmt::Display {
-        // todo: separate out the cpu dbg from the instr dbg;
-        // I think that makes more sense.
-
-        let next_instr = match self.next_instr(mem) {
-            Ok(instr) => instr,
-            Err(e) => {
-                let ret: Box<dyn fmt::Display> = Box::new(e);
-                return ret;
-            }
+    pub fn dbg_next_instr(&self, mem: &mut AddressSpace, symbols: &SymbolTable) -> disasm::Line {
+        let mut line = disasm::disassemble(mem, self.pc, symbols);
+        line.effective_addr = match self.next_instr(mem) {
+            Ok((_, mode, Operand::Memory { addr })) if disasm::is_computed(mode) => Some(addr),
+            _ => None,
         };
-
-        let mut next_instr_bytes = vec![];
-        for i in 0..next_instr.1.instr_len() {
-            let byte = mem.read(self.pc.checked_add(i).unwrap());
-            next_instr_bytes.push(byte);
-        }
-
-        Box::new(DbgNextInstr {
-            cpu: self.clone(),
-            next_instr,
-            next_instr_bytes,
-        })
-    }
-}
-
-pub struct DbgNextInstr {
-    cpu: Cpu,
-    next_instr: (Instr, Mode, Operand),
-    next_instr_bytes: Vec<u8>,
-}
-
-impl fmt::Display for DbgNextInstr {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:04x}:", self.cpu.pc)?;
-
-        for i in 0..3 {
-            if i < self.next_instr_bytes.len() {
-                let b = self.next_instr_bytes[i];
-                write!(f, " {:02x}", b)?;
-            } else {
-                write!(f, "   ")?;
-            }
-        }
-
-        write!(f, "{}", " ".repeat(5))?;
-
-        let (instr, mode, arg) = self.next_instr;
-        write!(f, "{:?}  {:10}  {:?}", instr, format!("{:?}", mode), arg)?;
-        // todo: surely there's a better way to get spacing right, but cba for now
-
-        Ok(())
+        line
     }
 }
@@ -0,0 +1,88 @@
+//! How many clock cycles each instruction takes.
+//!
+//! See e.g. <https://www.masswerk.at/6502/6502_instruction_set.html>.
+
+use super::instr::{Instr, Mode};
+
+/// The number of cycles, not counting any extra cycles for crossing a page
+/// boundary or taking a branch.
+pub fn base(instr: Instr, mode: Mode) -> u8 {
+    match instr {
+        Instr::Brk => return 7,
+        Instr::Pha | Instr::Php => return 3,
+        Instr::Pla | Instr::Plp => return 4,
+        Instr::Rts | Instr::Rti => return 6,
+        Instr::Jsr => return 6,
+        Instr::Jmp if mode == Mode::Absolute => return 3,
+        Instr::Jmp => return 5,
+        _ => (),
+    }
+
+    let read_modify_write = is_read_modify_write(instr);
+    let store = matches!(instr, Instr::Sta | Instr::Stx | Instr::Sty);
+
+    match mode {
+        Mode::Implied | Mode::Accumulator | Mode::Immediate | Mode::Relative => 2,
+
+        Mode::ZeroPage if read_modify_write => 5,
+        Mode::ZeroPage => 3,
+        Mode::ZeroPageX | Mode::ZeroPageY if read_modify_write => 6,
+        Mode::ZeroPageX | Mode::ZeroPageY => 4,
+
+        Mode::Absolute if read_modify_write => 6,
+        Mode::Absolute => 4,
+        Mode::AbsoluteX | Mode::AbsoluteY if read_modify_write => 7,
+        Mode::AbsoluteX | Mode::AbsoluteY if store => 5,
+        Mode::AbsoluteX | Mode::AbsoluteY => 4,
+
+        Mode::XIndirect => 6,
+        Mode::IndirectY if store => 6,
+        Mode::IndirectY => 5,
+
+        Mode::Indirect => unreachable!("only used by jmp"),
+    }
+}
+
+/// Does this instruction pay an extra cycle when indexing crosses a page
+/// boundary? (Writes always take the extra cycle, so they're already
+/// accounted for in `base`.)
+pub fn has_page_penalty(instr: Instr, mode: Mode) -> bool {
+    let indexed = matches!(mode, Mode::AbsoluteX | Mode::AbsoluteY | Mode::IndirectY);
+    let write = is_read_modify_write(instr) || matches!(instr, Instr::Sta | Instr::Stx | Instr::Sty);
+    indexed && !write
+}
+
+fn is_read_modify_write(instr: Instr) -> bool {
+    matches!(
+        instr,
+        Instr::Asl | Instr::Lsr | Instr::Rol | Instr::Ror | Instr::Inc | Instr::Dec
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+    use crate::cpu::instr::decode;
+
+    #[test_case(0xa9, 2; "lda immediate")]
+    #[test_case(0xad, 4; "lda absolute")]
+    #[test_case(0xbd, 4; "lda absolute x")]
+    #[test_case(0x9d, 5; "sta absolute x")]
+    #[test_case(0xb1, 5; "lda indirect y")]
+    #[test_case(0x91, 6; "sta indirect y")]
+    #[test_case(0xe6, 5; "inc zero page")]
+    #[test_case(0xfe, 7; "inc absolute x")]
+    #[test_case(0x4c, 3; "jmp absolute")]
+    #[test_case(0x6c, 5; "jmp indirect")]
+    #[test_case(0x20, 6; "jsr")]
+    #[test_case(0x60, 6; "rts")]
+    #[test_case(0x48, 3; "pha")]
+    #[test_case(0x68, 4; "pla")]
+    #[test_case(0xd0, 2; "bne")]
+    fn base_cycles(opcode: u8, expected: u8) {
+        let (instr, mode) = decode(opcode).unwrap();
+        assert_eq!(base(instr, mode), expected);
+    }
+}
@@ -0,0 +1,178 @@
+//! Turning machine code back into assembly, in the usual syntax. E.g.
+//! `LDA ($06),Y`.
+
+use std::fmt;
+
+use itertools::Itertools;
+
+use crate::{
+    cpu::instr::{self, Mode},
+    memory::AddressSpace,
+    symbols::{SourceLine, SymbolTable},
+};
+
+/// One instruction's worth of disassembly.
+pub struct Line {
+    pub addr: u16,
+    /// `None` for bytes we can't look at without side effects (i.e. I/O).
+    pub bytes: Vec<Option<u8>>,
+    /// E.g. `JSR COUT`, or `???` if it's not a valid opcode.
+    pub text: String,
+    /// For the next instruction (i.e. at the PC), where e.g. `LDA ($06),Y`
+    /// is actually going to load from. (`disassemble` doesn't know that, so
+    /// it's up to the caller.)
+    pub effective_addr: Option<u16>,
+    pub source: Option<SourceLine>,
+}
+
+impl Line {
+    /// Where the instruction after this one starts.
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.bytes.len() as u16)
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:", self.addr)?;
+        for i in 0..3 {
+            match self.bytes.get(i) {
+                Some(Some(b)) => write!(f, " {b:02x}")?,
+                Some(None) => write!(f, " ??")?,
+                None => write!(f, "   ")?,
+            }
+        }
+        write!(f, "     {}", self.text)?;
+
+        let mut comments = vec![];
+        if let Some(addr) = self.effective_addr {
+            comments.push(format!("${addr:04x}"));
+        }
+        if let Some(source) = &self.source {
+            comments.push(source.to_string());
+        }
+        if !comments.is_empty() {
+            let padding = 12_usize.saturating_sub(self.text.len());
+            write!(f, "{}  ; {}", " ".repeat(padding), comments.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Disassemble the instruction at `addr`. (Without any side effects, so it's
+/// fine to point this anywhere.)
+pub fn disassemble(mem: &AddressSpace, addr: u16, symbols: &SymbolTable) -> Line {
+    let source = symbols.line(addr).cloned();
+    let opcode = mem.peek(addr);
+    let Some(Ok((instr, mode))) = opcode.map(instr::decode) else {
+        return Line {
+            addr,
+            bytes: vec![opcode],
+            text: "???".to_string(),
+            effective_addr: None,
+            source,
+        };
+    };
+
+    let bytes = (0..mode.instr_len())
+        .map(|i| mem.peek(addr.wrapping_add(i)))
+        .collect_vec();
+    let arg = match bytes[1..] {
+        [] => Some(0),
+        [Some(lo)] => Some(lo as u16),
+        [Some(lo), Some(hi)] => Some(u16::from_le_bytes([lo, hi])),
+        _ => None,
+    };
+
+    let mnemonic = format!("{instr:?}").to_uppercase();
+    let text = match arg.map(|arg| operand(mode, arg, addr, symbols)) {
+        Some(operand) if operand.is_empty() => mnemonic,
+        Some(operand) => format!("{mnemonic} {operand}"),
+        None => format!("{mnemonic} ??"),
+    };
+    Line {
+        addr,
+        bytes,
+        text,
+        effective_addr: None,
+        source,
+    }
+}
+
+fn operand(mode: Mode, arg: u16, addr: u16, symbols: &SymbolTable) -> String {
+    let zero_page = |addr: u16| match symbols.name(addr) {
+        Some(name) => name.to_string(),
+        None => format!("${addr:02X}"),
+    };
+    let absolute = |addr: u16| match symbols.name(addr) {
+        Some(name) => name.to_string(),
+        None => format!("${addr:04X}"),
+    };
+
+    match mode {
+        Mode::Implied => String::new(),
+        Mode::Accumulator => "A".to_string(),
+
+        Mode::Immediate => format!("#${arg:02X}"),
+        // Show where it goes, not the offset.
+        Mode::Relative => absolute(addr.wrapping_add(2).wrapping_add(arg as u8 as i8 as u16)),
+
+        Mode::ZeroPage => zero_page(arg),
+        Mode::ZeroPageX => format!("{},X", zero_page(arg)),
+        Mode::ZeroPageY => format!("{},Y", zero_page(arg)),
+
+        Mode::XIndirect => format!("({},X)", zero_page(arg)),
+        Mode::IndirectY => format!("({}),Y", zero_page(arg)),
+
+        Mode::Absolute => absolute(arg),
+        Mode::AbsoluteX => format!("{},X", absolute(arg)),
+        Mode::AbsoluteY => format!("{},Y", absolute(arg)),
+
+        Mode::Indirect => format!("({})", absolute(arg)),
+    }
+}
+
+/// Whether the address an instruction ends up using depends on registers or
+/// memory, so it's worth showing what it is right now.
+pub fn is_computed(mode: Mode) -> bool {
+    matches!(
+        mode,
+        Mode::ZeroPageX
+            | Mode::ZeroPageY
+            | Mode::XIndirect
+            | Mode::IndirectY
+            | Mode::AbsoluteX
+            | Mode::AbsoluteY
+            | Mode::Indirect
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(&[0xa9, 0x8d], "LDA #$8D")]
+    #[test_case(&[0xbd, 0x00, 0xc0], "LDA $C000,X")]
+    #[test_case(&[0xb1, 0x06], "LDA ($06),Y")]
+    #[test_case(&[0xa1, 0x06], "LDA ($06,X)")]
+    #[test_case(&[0xb6, 0x06], "LDX $06,Y")]
+    #[test_case(&[0x6c, 0x36, 0x00], "JMP ($0036)")]
+    #[test_case(&[0x0a], "ASL A")]
+    #[test_case(&[0x60], "RTS")]
+    #[test_case(&[0xd0, 0xfe], "BNE $0300"; "branch backwards")]
+    #[test_case(&[0x20, 0xed, 0xfd], "JSR COUT"; "label")]
+    #[test_case(&[0x85, 0x24], "STA CH"; "zero page label")]
+    #[test_case(&[0x02], "???")]
+    fn disassemble_(program: &[u8], expected: &str) {
+        let mem = AddressSpace::new(program, 0x300);
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0xfded, "COUT");
+        symbols.insert(0x24, "CH");
+
+        let line = disassemble(&mem, 0x300, &symbols);
+        assert_eq!(line.text, expected);
+        assert_eq!(line.next_addr(), 0x300 + program.len() as u16);
+    }
+}
@@ -16,11 +16,14 @@ pub enum Operand {
 
 impl Operand {
     pub fn new(cpu: &Cpu, mem: &mut AddressSpace, mode: Mode) -> Self {
+        // (The instruction itself isn't data, so it doesn't count for
+        // watchpoints.)
+        let mut fetch = |i| mem.read_unwatched(cpu.pc.checked_add(i).unwrap());
         let arg_len = mode.instr_len() - 1;
         let arg: u16 = match arg_len {
             0 => 0,
-            1 => mem.read(cpu.pc.checked_add(1).unwrap()).into(),
-            2 => read_word(mem, cpu.pc.checked_add(1).unwrap()),
+            1 => fetch(1).into(),
+            2 => u16::from_le_bytes([fetch(1), fetch(2)]),
             _ => unreachable!(),
         };
 
@@ -104,7 +107,7 @@ fn checked_offset(addr: u16, offset: i8) -> Option<u16> {
         return addr.checked_add(offset as u16);
     }
 
-    let abs_offset = (offset as i16).abs() as u16;
+    let abs_offset = (offset as i16).unsigned_abs();
     addr.checked_sub(abs_offset)
 }
 
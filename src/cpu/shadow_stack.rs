@@ -0,0 +1,182 @@
+//! A record of the JSRs that haven't returned yet, for `bt`.
+//!
+//! The real stack is just bytes, so it's hard to tell return addresses from
+//! everything else. Instead, the CPU tells us about every JSR and RTS. When
+//! the program plays tricks with the stack (e.g. pushing an address and
+//! RTSing to it, which the 80-column firmware does), this notices, and
+//! remembers the most recent one.
+
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::save_state::Sections;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// The address of the JSR instruction.
+    pub call_site: u16,
+    /// The stack pointer after the JSR, so the return address is just above
+    /// this.
+    pub sp: u8,
+}
+
+impl Frame {
+    /// Where the RTS should go back to.
+    pub fn return_addr(&self) -> u16 {
+        self.call_site.wrapping_add(3)
+    }
+}
+
+/// When the real stack didn't do what the shadow stack expected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The address of the RTS.
+    pub pc: u16,
+    /// Where it went.
+    pub to: u16,
+    /// Where the shadow stack thought it'd go, if anywhere.
+    pub expected: Option<u16>,
+    /// How many frames there were at the time. Once we've returned past
+    /// those, it's old news.
+    depth: usize,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.expected {
+            Some(expected) => write!(
+                f,
+                "the RTS at ${:04x} went to ${:04x}, not ${expected:04x}",
+                self.pc, self.to
+            ),
+            None => write!(
+                f,
+                "the RTS at ${:04x} went to ${:04x}, without a JSR",
+                self.pc, self.to
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ShadowStack {
+    frames: Vec<Frame>,
+    last_mismatch: Option<Mismatch>,
+}
+
+impl ShadowStack {
+    /// Oldest first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn last_mismatch(&self) -> Option<Mismatch> {
+        self.last_mismatch
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.last_mismatch = None;
+    }
+
+    /// `sp` is after pushing the return address.
+    pub fn jsr(&mut self, call_site: u16, sp: u8) {
+        self.frames.push(Frame { call_site, sp });
+    }
+
+    /// `sp` is from before popping the return address, and `to` is where it
+    /// went.
+    pub fn rts(&mut self, pc: u16, sp: u8, to: u16) {
+        let expected = match self.frames.last() {
+            // Returning from the latest JSR, as usual.
+            Some(frame) if frame.sp == sp => Some(frame.return_addr()),
+            // Something else was pushed since then (or there was no JSR).
+            _ => None,
+        };
+        if expected != Some(to) {
+            self.last_mismatch = Some(Mismatch {
+                pc,
+                to,
+                expected,
+                depth: self.frames.len(),
+            });
+        }
+    }
+
+    /// Forget the frames that aren't on the stack any more. Called whenever
+    /// the stack pointer might have gone up (RTS, but also e.g. PLA, or TXS).
+    pub fn unwind(&mut self, sp: u8) {
+        while self.frames.last().is_some_and(|frame| frame.sp < sp) {
+            self.frames.pop();
+        }
+        if self
+            .last_mismatch
+            .is_some_and(|mismatch| self.frames.len() < mismatch.depth)
+        {
+            self.last_mismatch = None;
+        }
+    }
+
+    pub fn save_state(&self, sections: &mut Sections) {
+        let mut data = vec![];
+        for frame in &self.frames {
+            data.extend(frame.call_site.to_le_bytes());
+            data.push(frame.sp);
+        }
+        sections.add(b"CALL", data);
+    }
+
+    /// Older save states don't have one, so it just starts out empty.
+    pub fn load_state(&mut self, sections: &Sections) -> Result<()> {
+        self.clear();
+        let Some(mut r) = sections.find(b"CALL") else {
+            return Ok(());
+        };
+        while !r.is_empty() {
+            self.frames.push(Frame {
+                call_site: r.u16()?,
+                sp: r.u8()?,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jsr_and_rts() {
+        let mut stack = ShadowStack::default();
+        stack.jsr(0x300, 0xfd);
+        stack.jsr(0x410, 0xfb);
+
+        // Returning as usual.
+        stack.rts(0x510, 0xfb, 0x413);
+        stack.unwind(0xfd);
+        assert_eq!(stack.frames().len(), 1);
+        assert_eq!(stack.last_mismatch(), None);
+
+        // Pushing an address, and RTSing to it.
+        stack.rts(0x420, 0xfb, 0x600);
+        stack.unwind(0xfd);
+        assert_eq!(stack.frames().len(), 1);
+        assert_eq!(
+            stack.last_mismatch(),
+            Some(Mismatch {
+                pc: 0x420,
+                to: 0x600,
+                expected: None,
+                depth: 1,
+            })
+        );
+
+        // Throwing away the return address (with PLA PLA), and jumping. (And
+        // then the trick above doesn't matter any more.)
+        stack.unwind(0xff);
+        assert!(stack.frames().is_empty());
+        assert_eq!(stack.last_mismatch(), None);
+    }
+}
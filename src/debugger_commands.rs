@@ -1,27 +1,128 @@
-use std::str::FromStr;
+mod expr;
+
+use std::{fmt, ops::RangeInclusive, str::FromStr};
 
 use anyhow::{bail, ensure, Context, Result};
+pub use expr::Expr;
 use itertools::Itertools;
 
-use crate::{hex, memory::AddressSpace, Emulator};
+use crate::{
+    cpu::{disasm, instr::Instr, Register},
+    hex,
+    memory::AddressSpace,
+    save_state, Emulator, Monitor, WatchKind, Watchpoint,
+};
+
+/// How many instructions `list` shows. (The same as the monitor's `L`.)
+const LIST_LEN: usize = 20;
+/// How many instructions `history` shows, by default.
+const HISTORY_LEN: usize = 20;
 
 /// CLI debugger command.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Command {
     Halt,
     Continue,
     CpuInfo,
 
-    Step,
-    ToggleBreakpoint { addr: u16 },
+    Step {
+        count: u32,
+    },
+    Next,
+    Until {
+        addr: Location,
+    },
+    Goto {
+        addr: Location,
+    },
+    Call {
+        addr: Location,
+    },
+    ReverseStep,
+    ReverseContinue,
+    ToggleBreakpoint {
+        addr: Location,
+    },
+    SetBreakpoint {
+        addr: Location,
+        condition: Option<Expr>,
+        ignore_count: u32,
+    },
+    ListBreakpoints,
+    Backtrace,
+    History {
+        count: usize,
+    },
+    ToggleWatchpoint {
+        watchpoint: Watchpoint,
+    },
     Finish,
 
-    ShowByte { addr: u16 },
-    ShowRange { start: u16, end_inclusive: u16 },
-    // other ideas for commands:
-    // * goto (set pc)
-    // * jsr (which auto-breaks when we return all the way back)
-    // * step `n` times
+    ShowByte {
+        addr: u16,
+    },
+    Print {
+        expr: Expr,
+    },
+    Deposit {
+        addr: u16,
+        bytes: Vec<u8>,
+        raw: bool,
+    },
+    Set {
+        target: Expr,
+        value: Option<Expr>,
+    },
+    List {
+        /// Defaults to the PC.
+        addr: Option<Location>,
+        count: usize,
+    },
+    ShowRange {
+        start: u16,
+        end_inclusive: u16,
+    },
+
+    SetMonitor {
+        monitor: Monitor,
+    },
+    Screenshot {
+        path: String,
+        scale: usize,
+    },
+    LoadSymbols {
+        path: String,
+    },
+    StartRecording {
+        path: String,
+    },
+    StopRecording,
+    StartInputRecording {
+        path: String,
+    },
+    StopInputRecording,
+    /// Without a path, carries on with the current trace (or starts one in
+    /// `trace.log`).
+    StartTrace {
+        path: Option<String>,
+        range: Option<RangeInclusive<u16>>,
+        /// Add the symbol and source line at the end of each line.
+        symbols: bool,
+    },
+    StopTrace,
+    ReplayInput {
+        path: String,
+    },
+    RamHash,
+    Type {
+        text: String,
+    },
+    SaveState {
+        slot: u32,
+    },
+    LoadState {
+        slot: u32,
+    },
 }
 
 impl FromStr for Command {
@@ -34,19 +135,263 @@ impl FromStr for Command {
             "h" | "halt" => return Ok(Command::Halt),
             "c" | "continue" => return Ok(Command::Continue),
             "i" | "info" => return Ok(Command::CpuInfo),
-            "s" | "step" => return Ok(Command::Step),
+            "s" | "step" => return Ok(Command::Step { count: 1 }),
+            "n" | "next" => return Ok(Command::Next),
             "f" | "finish" => return Ok(Command::Finish),
+            "rs" | "reverse-step" => return Ok(Command::ReverseStep),
+            "rc" | "reverse-continue" => return Ok(Command::ReverseContinue),
+            "hash" => return Ok(Command::RamHash),
+            "bl" | "breakpoints" => return Ok(Command::ListBreakpoints),
+            "bt" | "backtrace" => return Ok(Command::Backtrace),
+            "history" => return Ok(Command::History { count: HISTORY_LEN }),
+            "l" | "list" => {
+                return Ok(Command::List {
+                    addr: None,
+                    count: LIST_LEN,
+                })
+            }
             _ => (),
         }
 
+        // Unlike the other commands, keep the whitespace.
+        if let Some(text) = s.strip_prefix("type ") {
+            return Ok(Command::Type {
+                text: unescape(text),
+            });
+        }
+
+        // Monitor-style deposits: `300: a9 00 60`. Or `patch c511: 3f`, which
+        // bypasses the bus (and any side effects), and can even patch ROM.
+        let (raw, deposit) = match s.strip_prefix("patch ") {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        if let Some((addr, values)) = deposit.split_once(':') {
+            if let Ok(addr) = hex::decode_u16(addr.trim()) {
+                let bytes = parse_deposit(values)?;
+                return Ok(Command::Deposit { addr, bytes, raw });
+            }
+        }
+        ensure!(!raw, "expected patch <addr>: <bytes>");
+
         let mut words = s.split_whitespace();
         let first = words.next().context("empty command")?;
-        if matches!(first, "b" | "break") {
+        let rest = s[first.len()..].trim();
+
+        if matches!(first, "p" | "print") {
+            return Ok(Command::Print {
+                expr: rest.parse()?,
+            });
+        }
+
+        if matches!(first, "s" | "step") {
+            let (count,) = words
+                .collect_tuple()
+                .context("expected 0 or 1 arguments to step")?;
+            let count = count.parse().context("invalid count")?;
+            return Ok(Command::Step { count });
+        }
+
+        if matches!(first, "u" | "until" | "goto" | "call") {
             let (addr,) = words
                 .collect_tuple()
-                .context("expected 1 argument to break")?;
-            let addr = hex::decode_u16(addr)?;
-            return Ok(Command::ToggleBreakpoint { addr });
+                .with_context(|| format!("expected 1 argument to {first}"))?;
+            let addr = addr.parse()?;
+            return Ok(match first {
+                "goto" => Command::Goto { addr },
+                "call" => Command::Call { addr },
+                _ => Command::Until { addr },
+            });
+        }
+
+        if matches!(first, "l" | "list") {
+            let args = words.collect_vec();
+            let (addr, count) = match args[..] {
+                [addr] => (addr, LIST_LEN),
+                [addr, count] => (addr, count.parse().context("invalid count")?),
+                _ => bail!("expected: list [<addr> [count]]"),
+            };
+            return Ok(Command::List {
+                addr: Some(addr.parse()?),
+                count,
+            });
+        }
+
+        // E.g. `set a=$12`, `set c=1`, or `set c` to toggle a flag.
+        if first == "set" {
+            let (target, value) = match rest.split_once('=') {
+                Some((target, value)) => (target, Some(value.parse()?)),
+                None => (rest, None),
+            };
+            let target = target.parse()?;
+            match (&target, &value) {
+                (Expr::Register(_) | Expr::Byte(_), Some(_)) | (Expr::Flag(_), _) => (),
+                (Expr::Register(_) | Expr::Byte(_), None) => bail!("expected set {target}=<value>"),
+                _ => bail!("can only set a register, a flag, or a byte of memory (e.g. [$300])"),
+            }
+            return Ok(Command::Set { target, value });
+        }
+
+        if matches!(first, "b" | "break") {
+            let (rest, condition) = match rest.split_once(" if ") {
+                Some((rest, condition)) => (rest, Some(condition.parse()?)),
+                None => (rest, None),
+            };
+            let args = rest.split_whitespace().collect_vec();
+            let (addr, ignore_count) = match args[..] {
+                [addr] => (addr, None),
+                [addr, "ignore", n] => (addr, Some(n.parse().context("invalid ignore count")?)),
+                _ => bail!("expected: break <addr> [ignore <n>] [if <condition>]"),
+            };
+            let addr = addr.parse()?;
+
+            if condition.is_none() && ignore_count.is_none() {
+                return Ok(Command::ToggleBreakpoint { addr });
+            }
+            return Ok(Command::SetBreakpoint {
+                addr,
+                condition,
+                ignore_count: ignore_count.unwrap_or(0),
+            });
+        }
+
+        if first == "history" {
+            let (count,) = words
+                .collect_tuple()
+                .context("expected 1 argument to history: [count]")?;
+            let count = count.parse().context("invalid count")?;
+            return Ok(Command::History { count });
+        }
+
+        let watch_kind = match first {
+            "watch" => Some(WatchKind::Write),
+            "rwatch" => Some(WatchKind::Read),
+            "awatch" => Some(WatchKind::Access),
+            _ => None,
+        };
+        if let Some(kind) = watch_kind {
+            let (range,) = words.collect_tuple().with_context(|| {
+                format!("expected 1 argument to {first}: <addr> or <start>.<end>")
+            })?;
+            let (start, end_inclusive) = match range.split_once('.') {
+                Some((start, end)) => (hex::decode_u16(start)?, hex::decode_u16(end)?),
+                None => {
+                    let addr = hex::decode_u16(range)?;
+                    (addr, addr)
+                }
+            };
+            ensure!(start <= end_inclusive);
+            return Ok(Command::ToggleWatchpoint {
+                watchpoint: Watchpoint {
+                    start,
+                    end_inclusive,
+                    kind,
+                },
+            });
+        }
+
+        if first == "monitor" {
+            let (monitor,) = words
+                .collect_tuple()
+                .context("expected 1 argument to monitor")?;
+            let monitor = monitor.parse()?;
+            return Ok(Command::SetMonitor { monitor });
+        }
+
+        if first == "screenshot" {
+            let args = words.collect_vec();
+            let (path, scale) = match args[..] {
+                [path] => (path, 1),
+                [path, scale] => (path, scale.parse().context("invalid scale")?),
+                _ => bail!("expected 1 or 2 arguments to screenshot: <file.png> [scale]"),
+            };
+            return Ok(Command::Screenshot {
+                path: path.to_string(),
+                scale,
+            });
+        }
+
+        if matches!(first, "save" | "load") {
+            let slot = match words.collect_tuple() {
+                Some((slot,)) => slot.parse().context("invalid slot number")?,
+                None => 1,
+            };
+            return Ok(if first == "save" {
+                Command::SaveState { slot }
+            } else {
+                Command::LoadState { slot }
+            });
+        }
+
+        if first == "symbols" {
+            let (path,) = words
+                .collect_tuple()
+                .context("expected 1 argument to symbols: <file>")?;
+            return Ok(Command::LoadSymbols {
+                path: path.to_string(),
+            });
+        }
+
+        if first == "record" {
+            let (arg,) = words
+                .collect_tuple()
+                .context("expected 1 argument to record: <file.y4m>, <directory>, or stop")?;
+            if arg == "stop" {
+                return Ok(Command::StopRecording);
+            }
+            return Ok(Command::StartRecording {
+                path: arg.to_string(),
+            });
+        }
+
+        if first == "record-input" {
+            let (arg,) = words
+                .collect_tuple()
+                .context("expected 1 argument to record-input: <file> or stop")?;
+            if arg == "stop" {
+                return Ok(Command::StopInputRecording);
+            }
+            return Ok(Command::StartInputRecording {
+                path: arg.to_string(),
+            });
+        }
+
+        if first == "trace" {
+            let mut args = words.collect_vec();
+            let symbols = args.len() > 1 && args.last() == Some(&"symbols");
+            if symbols {
+                args.pop();
+            }
+            let (arg, range) = match args[..] {
+                [arg] => (arg, None),
+                [arg, range] => (arg, Some(parse_range(range)?)),
+                _ => bail!(
+                    "expected 1 to 3 arguments to trace: on|off|<file> [<start>.<end>] [symbols]"
+                ),
+            };
+            return Ok(match arg {
+                "off" if range.is_none() && !symbols => Command::StopTrace,
+                "off" => bail!("trace off doesn't take any more arguments"),
+                "on" => Command::StartTrace {
+                    path: None,
+                    range,
+                    symbols,
+                },
+                path => Command::StartTrace {
+                    path: Some(path.to_string()),
+                    range,
+                    symbols,
+                },
+            });
+        }
+
+        if first == "replay-input" {
+            let (path,) = words
+                .collect_tuple()
+                .context("expected 1 argument to replay-input")?;
+            return Ok(Command::ReplayInput {
+                path: path.to_string(),
+            });
         }
 
         if s.contains('.') {
@@ -64,6 +409,16 @@ impl FromStr for Command {
             return Ok(Command::ShowByte { addr });
         }
 
+        // Monitor-style: `300L`.
+        if let Some(addr) = s.strip_suffix(['l', 'L']) {
+            if let Ok(addr) = hex::decode_u16(addr) {
+                return Ok(Command::List {
+                    addr: Some(Location::Addr(addr)),
+                    count: LIST_LEN,
+                });
+            }
+        }
+
         bail!("invalid command: {s:?}");
     }
 }
@@ -76,7 +431,7 @@ impl Command {
                     println!("already halted");
                 } else {
                     emu.halted = true;
-                    println!("{}", emu.cpu.dbg_next_instr(&mut emu.mem));
+                    println!("{}", emu.cpu.dbg_next_instr(&mut emu.mem, &emu.symbols));
                 }
             }
             Command::Continue => {
@@ -87,9 +442,8 @@ impl Command {
 
                     // Skip past the current breakpoint. (Instead of breaking
                     // right away and going nowhere.)
-                    if emu.breakpoints.contains(&emu.cpu.pc()) {
-                        emu.cpu.step(&mut emu.mem);
-                        emu.num_instructions_executed += 1;
+                    if emu.breakpoints.iter().any(|bp| bp.addr == emu.cpu.pc()) {
+                        emu.execute_instr();
                     }
                 }
             }
@@ -97,24 +451,175 @@ impl Command {
                 println!("{:?}", emu.cpu);
             }
 
-            Command::Step => {
+            Command::Step { count } => {
                 if !emu.halted {
                     println!("halting");
                     emu.halted = true;
                 }
-                emu.cpu.step(&mut emu.mem);
-                emu.num_instructions_executed += 1;
+                for i in 0..count {
+                    // (Ignoring any breakpoint we're already sitting on.)
+                    if i > 0 && emu.check_breakpoints().is_break() {
+                        break;
+                    }
+                    // So we can tell if a watchpoint halts us.
+                    emu.halted = false;
+                    emu.execute_instr();
+                    if emu.halted {
+                        break;
+                    }
+                }
+                emu.halted = true;
+
+                println!("{}", emu.cpu.dbg_next_instr(&mut emu.mem, &emu.symbols));
+            }
+            Command::Next => {
+                if !emu.halted {
+                    println!("already running; please halt first");
+                    return;
+                }
+
+                let pc = emu.cpu.pc();
+                let Ok((Instr::Jsr, mode, _)) = emu.cpu.next_instr(&mut emu.mem) else {
+                    // Nothing to step over.
+                    emu.execute_instr();
+                    println!("{}", emu.cpu.dbg_next_instr(&mut emu.mem, &emu.symbols));
+                    return;
+                };
+
+                // Run until we're back at the same stack depth. (Unlike
+                // `finish`, this still works when the subroutine plays tricks
+                // with the stack, as long as it comes back eventually.)
+                emu.stop_at = Some(StopAt {
+                    pc: pc.wrapping_add(mode.instr_len()),
+                    sp: Some(emu.cpu.register(Register::Sp) as u8),
+                    message: "stepped over subroutine",
+                });
+                emu.resume_with_one_instr();
+            }
+            Command::Until { addr } => {
+                let Some(addr) = emu.resolve(&addr) else {
+                    return;
+                };
+                emu.stop_at = Some(StopAt {
+                    pc: addr,
+                    sp: None,
+                    message: "reached",
+                });
+                if emu.halted {
+                    // Get going, even if we're already there. (E.g. to go
+                    // around a loop once.)
+                    emu.resume_with_one_instr();
+                }
+            }
+            Command::Goto { addr } => {
+                let Some(addr) = emu.resolve(&addr) else {
+                    return;
+                };
+                emu.cpu.set_register(Register::Pc, addr).unwrap();
+                println!("{}", emu.cpu.dbg_next_instr(&mut emu.mem, &emu.symbols));
+            }
+            Command::Call { addr } => {
+                if !emu.halted {
+                    println!("already running; please halt first");
+                    return;
+                }
+                let Some(addr) = emu.resolve(&addr) else {
+                    return;
+                };
 
-                println!("{}", emu.cpu.dbg_next_instr(&mut emu.mem));
+                // Like a JSR from right here, so we'll know it's returned
+                // when we get back here at the same stack depth.
+                let pc = emu.cpu.pc();
+                let sp = emu.cpu.register(Register::Sp) as u8;
+                emu.cpu.jsr(&mut emu.mem, addr);
+                emu.stop_at = Some(StopAt {
+                    pc,
+                    sp: Some(sp),
+                    message: "returned",
+                });
+                emu.halted = false;
+            }
+            Command::ReverseStep => {
+                emu.halted = true;
+                match emu.reverse_step() {
+                    Ok(()) => println!("{}", emu.cpu.dbg_next_instr(&mut emu.mem, &emu.symbols)),
+                    Err(e) => println!("can't step back: {e}"),
+                }
+            }
+            Command::ReverseContinue => {
+                emu.halted = true;
+                match emu.reverse_continue() {
+                    Ok(true) => println!("hit breakpoint"),
+                    Ok(false) => println!("no breakpoint; went back as far as possible"),
+                    Err(e) => println!("can't go back: {e}"),
+                }
+                println!("{}", emu.cpu.dbg_next_instr(&mut emu.mem, &emu.symbols));
             }
 
             Command::ToggleBreakpoint { addr } => {
-                if let Some((idx, _)) = emu.breakpoints.iter().find_position(|&&a| a == addr) {
+                let Some(addr) = emu.resolve(&addr) else {
+                    return;
+                };
+                let name = symbol_suffix(emu, addr);
+                if let Some((idx, _)) = emu.breakpoints.iter().find_position(|bp| bp.addr == addr) {
                     emu.breakpoints.swap_remove(idx);
-                    println!("cleared breakpoint ${:04x}", addr);
+                    println!("cleared breakpoint ${addr:04x}{name}");
                 } else {
-                    emu.breakpoints.push(addr);
-                    println!("set breakpoint ${:04x}", addr);
+                    emu.breakpoints.push(Breakpoint::new(addr));
+                    println!("set breakpoint ${addr:04x}{name}");
+                }
+            }
+            Command::SetBreakpoint {
+                addr,
+                condition,
+                ignore_count,
+            } => {
+                let Some(addr) = emu.resolve(&addr) else {
+                    return;
+                };
+                let breakpoint = Breakpoint {
+                    addr,
+                    condition,
+                    ignore_count,
+                };
+                // (Replacing any old one at the same address.)
+                emu.breakpoints.retain(|bp| bp.addr != addr);
+                println!("set breakpoint {breakpoint}{}", symbol_suffix(emu, addr));
+                emu.breakpoints.push(breakpoint);
+            }
+            Command::ListBreakpoints => {
+                if emu.breakpoints.is_empty() {
+                    println!("no breakpoints");
+                }
+                for bp in &emu.breakpoints {
+                    println!("{bp}{}", symbol_suffix(emu, bp.addr));
+                }
+            }
+
+            Command::Backtrace => backtrace(emu),
+            Command::History { count } => {
+                let mut entries = emu.history.last(count).peekable();
+                if entries.peek().is_none() {
+                    println!("no instructions yet");
+                }
+                for entry in entries {
+                    println!(
+                        "a:{:02x} x:{:02x} y:{:02x} sp:{:02x} p:{:02x}  {}",
+                        entry.a,
+                        entry.x,
+                        entry.y,
+                        entry.sp,
+                        entry.p,
+                        disasm::disassemble(&emu.mem, entry.pc, &emu.symbols)
+                    );
+                }
+            }
+
+            Command::ToggleWatchpoint { watchpoint } => {
+                if emu.mem.toggle_watchpoint(watchpoint) {
+                    println!("set {watchpoint}");
+                } else {
+                    println!("cleared {watchpoint}");
                 }
             }
 
@@ -136,12 +641,391 @@ impl Command {
             Command::ShowByte { addr } => {
                 println!("ram[${:04x}]: ${:02x}", addr, emu.mem.read(addr));
             }
+            Command::Print { expr } => match expr.eval(emu) {
+                Ok(value) => println!("{}", format_value(value)),
+                Err(e) => println!("{e}"),
+            },
+            Command::Deposit { addr, bytes, raw } => {
+                for (i, &byte) in bytes.iter().enumerate() {
+                    let addr = addr.wrapping_add(i as u16);
+                    if !raw {
+                        emu.mem.write(addr, byte);
+                    } else if let Err(e) = emu.mem.patch(addr, byte) {
+                        println!("{e}");
+                        return;
+                    }
+                }
+            }
+            Command::Set { target, value } => match set(emu, &target, value.as_ref()) {
+                Ok(value) => println!("{target} = {}", format_value(value)),
+                Err(e) => println!("{e}"),
+            },
+            Command::List { addr, count } => {
+                let mut addr = match addr {
+                    Some(addr) => match emu.resolve(&addr) {
+                        Some(addr) => addr,
+                        None => return,
+                    },
+                    None => emu.cpu.pc(),
+                };
+                for _ in 0..count {
+                    if let Some(name) = emu.symbols.name(addr) {
+                        println!("{name}:");
+                    }
+                    let line = disasm::disassemble(&emu.mem, addr, &emu.symbols);
+                    println!("{line}");
+                    addr = line.next_addr();
+                }
+            }
             Command::ShowRange {
                 start,
                 end_inclusive,
             } => show_range(&mut emu.mem, start, end_inclusive),
+
+            Command::SetMonitor { monitor } => {
+                emu.set_monitor(monitor);
+                println!("monitor: {monitor:?}");
+            }
+            Command::Screenshot { path, scale } => match emu.save_screenshot(&path, scale) {
+                Ok(()) => println!("saved {path}"),
+                Err(e) => println!("failed to save screenshot: {e}"),
+            },
+            Command::LoadSymbols { path } => match emu.load_symbols(&path) {
+                Ok(n) => println!("loaded {n} symbols from {path}"),
+                Err(e) => println!("{e:#}"),
+            },
+            Command::StartRecording { path } => match emu.start_recording(&path) {
+                Ok(()) => println!("recording to {path}"),
+                Err(e) => println!("failed to start recording: {e}"),
+            },
+            Command::StartInputRecording { path } => match emu.start_input_recording(&path) {
+                Ok(()) => println!("recording input to {path}"),
+                Err(e) => println!("failed to start recording input: {e}"),
+            },
+            Command::StopInputRecording => match emu.stop_input_recording() {
+                Ok(Some(n)) => println!("recorded {n} inputs (RAM hash {:016x})", emu.ram_hash()),
+                Ok(None) => println!("not recording input"),
+                Err(e) => println!("failed to finish recording input: {e}"),
+            },
+            Command::StartTrace {
+                path,
+                range,
+                symbols,
+            } => {
+                let result = match path {
+                    Some(path) => emu.start_trace(path, range.unwrap_or(0..=0xffff), symbols),
+                    None => emu.resume_trace(range, symbols),
+                };
+                match result {
+                    Ok(path) => println!("tracing to {}", path.display()),
+                    Err(e) => println!("failed to start tracing: {e:#}"),
+                }
+            }
+            Command::StopTrace => match emu.pause_trace() {
+                Ok(true) => println!("stopped tracing"),
+                Ok(false) => println!("not tracing"),
+                Err(e) => println!("failed to write the trace: {e}"),
+            },
+            Command::ReplayInput { path } => match emu.replay_input(&path) {
+                Ok(()) => println!("replaying {path}"),
+                Err(e) => println!("failed to replay: {e:#}"),
+            },
+            Command::RamHash => println!("{:016x}", emu.ram_hash()),
+            Command::Type { text } => emu.type_text(&text),
+            Command::SaveState { slot } => {
+                let path = save_state::slot_path(slot);
+                match emu.save_state(&path) {
+                    Ok(()) => println!("saved {}", path.display()),
+                    Err(e) => println!("failed to save state: {e}"),
+                }
+            }
+            Command::LoadState { slot } => {
+                let path = save_state::slot_path(slot);
+                match emu.load_state(&path) {
+                    Ok(()) => println!("{}", emu.cpu.dbg_next_instr(&mut emu.mem, &emu.symbols)),
+                    Err(e) => println!("failed to load {}: {e}", path.display()),
+                }
+            }
+            Command::StopRecording => match emu.stop_recording() {
+                Ok(Some(frames)) => println!("recorded {frames} frames"),
+                Ok(None) => println!("not recording"),
+                Err(e) => println!("failed to finish recording: {e}"),
+            },
+        }
+    }
+}
+
+/// An address, or the name of a symbol. (Names get looked up when the
+/// command runs, since you can load more symbols at any time.)
+#[derive(Debug, Clone)]
+pub enum Location {
+    Addr(u16),
+    Symbol(String),
+}
+
+impl FromStr for Location {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // E.g. `$fded`, or `0803`. (But `fded` could be a name, so leave that
+        // for later.)
+        if s.starts_with(|c: char| c == '$' || c.is_ascii_digit()) {
+            return Ok(Location::Addr(hex::decode_u16(s)?));
+        }
+        ensure!(
+            s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_.@:".contains(c)),
+            "not an address or a symbol: {s:?}"
+        );
+        Ok(Location::Symbol(s.to_string()))
+    }
+}
+
+impl Emulator {
+    /// Prints what went wrong, if it doesn't.
+    fn resolve(&self, location: &Location) -> Option<u16> {
+        match location {
+            Location::Addr(addr) => Some(*addr),
+            Location::Symbol(name) => match self.symbols.resolve(name) {
+                Ok(addr) => Some(addr),
+                Err(e) => {
+                    println!("{e}");
+                    None
+                }
+            },
+        }
+    }
+
+    /// Run the next instruction without checking breakpoints, and then keep
+    /// running. Unless that instruction hits a watchpoint, in which case we
+    /// stay halted and forget about `stop_at`.
+    fn resume_with_one_instr(&mut self) {
+        self.halted = false;
+        self.execute_instr();
+        if self.halted {
+            self.stop_at = None;
+            println!("{}", self.cpu.dbg_next_instr(&mut self.mem, &self.symbols));
+        }
+    }
+}
+
+/// Innermost first, like gdb.
+fn backtrace(emu: &Emulator) {
+    let shadow_stack = emu.cpu.shadow_stack();
+    let sp = emu.cpu.register(Register::Sp) as u8;
+    // What's really on the stack, just above `sp`.
+    let stacked_addr = |sp: u8| {
+        let lo = emu.mem.peek(0x100 + sp.wrapping_add(1) as u16)?;
+        let hi = emu.mem.peek(0x100 + sp.wrapping_add(2) as u16)?;
+        Some(u16::from_le_bytes([lo, hi]).wrapping_add(1))
+    };
+
+    println!("#0  {}", describe(emu, emu.cpu.pc()));
+    if shadow_stack.frames().is_empty() {
+        // E.g. after loading an old save state. So, look for anything on the
+        // stack that points just after a JSR.
+        println!("(no calls recorded; guessing from the stack)");
+        let mut i = 1;
+        let mut sp = sp;
+        while sp < 0xfe {
+            let Some(return_addr) = stacked_addr(sp) else {
+                break;
+            };
+            let call_site = return_addr.wrapping_sub(3);
+            if emu.mem.peek(call_site) == Some(0x20) {
+                println!("#{i}  {}?", describe(emu, call_site));
+                i += 1;
+                sp += 2;
+            } else {
+                sp += 1;
+            }
+        }
+        return;
+    }
+
+    for (i, frame) in shadow_stack.frames().iter().rev().enumerate() {
+        print!("#{}  {}", i + 1, describe(emu, frame.call_site));
+        match stacked_addr(frame.sp) {
+            Some(addr) if addr == frame.return_addr() => println!(),
+            Some(addr) => println!("    <- but the stack says it returns to ${addr:04x}"),
+            None => println!(),
         }
     }
+    if let Some(mismatch) = shadow_stack.last_mismatch() {
+        println!("note: {mismatch}, so this may not be the whole story");
+    }
+}
+
+/// E.g. `$0803 in main+$3 (hello.c:12)`.
+fn describe(emu: &Emulator, addr: u16) -> String {
+    let mut s = format!("${addr:04x}");
+    if let Some(name) = emu.symbols.describe(addr) {
+        s += &format!(" in {name}");
+    }
+    if let Some(line) = emu.symbols.line_containing(addr) {
+        s += &format!(" ({line})");
+    }
+    s
+}
+
+/// E.g. ` (main)`, if there's a name for `addr`.
+fn symbol_suffix(emu: &Emulator, addr: u16) -> String {
+    match emu.symbols.name(addr) {
+        Some(name) => format!(" ({name})"),
+        None => String::new(),
+    }
+}
+
+/// Where `next`, `until`, and `call` stop.
+#[derive(Debug, Clone, Copy)]
+pub struct StopAt {
+    pub pc: u16,
+    /// If set, only stop when the stack pointer is here too. (So e.g. a
+    /// recursive call doesn't count.)
+    pub sp: Option<u8>,
+    pub message: &'static str,
+}
+
+/// A breakpoint, with an optional condition. E.g. `break fded if a == $8d`.
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub condition: Option<Expr>,
+    /// How many more times to skip it (when the condition holds), before
+    /// actually breaking.
+    pub ignore_count: u32,
+}
+
+impl Breakpoint {
+    pub fn new(addr: u16) -> Self {
+        Self {
+            addr,
+            condition: None,
+            ignore_count: 0,
+        }
+    }
+
+    /// If the condition can't be evaluated (e.g. it peeks at I/O), that
+    /// counts as true, so you get to see what went wrong.
+    pub fn condition_holds(&self, emu: &Emulator) -> bool {
+        let Some(condition) = &self.condition else {
+            return true;
+        };
+        match condition.eval(emu) {
+            Ok(value) => value != 0,
+            Err(e) => {
+                eprintln!("\nbreakpoint condition failed: {e}");
+                true
+            }
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "${:04x}", self.addr)?;
+        if let Some(condition) = &self.condition {
+            write!(f, " if {condition}")?;
+        }
+        if self.ignore_count > 0 {
+            write!(f, " (ignoring the next {} hits)", self.ignore_count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the new value.
+fn set(emu: &mut Emulator, target: &Expr, value: Option<&Expr>) -> Result<i64> {
+    let value = match value {
+        Some(value) => value.eval(emu)?,
+        // Toggle.
+        None => (target.eval(emu)? == 0) as i64,
+    };
+
+    match target {
+        Expr::Register(register) => {
+            let value = u16::try_from(value)
+                .ok()
+                .with_context(|| format!("out of range: {value}"))?;
+            emu.cpu.set_register(*register, value)?;
+        }
+        Expr::Flag(flag) => emu.cpu.set_flag(*flag, value != 0),
+        Expr::Byte(addr) => {
+            let addr = addr.eval(emu)?;
+            let addr = u16::try_from(addr)
+                .ok()
+                .with_context(|| format!("not an address: {addr}"))?;
+            let value = u8::try_from(value)
+                .ok()
+                .with_context(|| format!("not a byte: {value}"))?;
+            emu.mem.write(addr, value);
+            return Ok(value as i64);
+        }
+        _ => unreachable!(),
+    }
+    target.eval(emu)
+}
+
+/// `<start>.<end>`, e.g. `800.bfff`.
+pub fn parse_range(s: &str) -> Result<RangeInclusive<u16>> {
+    let (start, end) = s
+        .split_once('.')
+        .context("expected a range: <start>.<end>")?;
+    let range = hex::decode_u16(start)?..=hex::decode_u16(end)?;
+    ensure!(!range.is_empty(), "the range is backwards");
+    Ok(range)
+}
+
+/// The bytes for a deposit: hex bytes, and "strings". (Strings get the hibit
+/// set, like Apple II text.)
+fn parse_deposit(s: &str) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        if let Some(string) = rest.strip_prefix('"') {
+            let (string, after) = string.split_once('"').context("unterminated string")?;
+            ensure!(string.is_ascii(), "strings have to be ASCII");
+            bytes.extend(string.bytes().map(|b| b | 0x80));
+            rest = after.trim_start();
+        } else {
+            let (word, after) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let byte = hex::decode_u16(word)?;
+            ensure!(byte <= 0xff, "not a byte: {word}");
+            bytes.push(byte as u8);
+            rest = after.trim_start();
+        }
+    }
+    ensure!(!bytes.is_empty(), "nothing to deposit");
+    Ok(bytes)
+}
+
+/// E.g. `$8d (141)`.
+fn format_value(value: i64) -> String {
+    match value {
+        0..=0xff => format!("${value:02x} ({value})"),
+        0x100..=0xffff => format!("${value:04x} ({value})"),
+        _ if value > 0 => format!("${value:x} ({value})"),
+        _ => format!("{value}"),
+    }
+}
+
+/// Handle `\r`, `\n`, and `\\`, so you can type e.g. `RUN\r`.
+pub fn unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
 }
 
 fn show_range(mem: &mut AddressSpace, start: u16, end_inclusive: u16) {
@@ -174,3 +1058,193 @@ fn show_range(mem: &mut AddressSpace, start: u16, end_inclusive: u16) {
 
     println!();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deposits() {
+        let bytes = parse_deposit(r#" a9 $12 0x60 "Hi!" 0 "#).unwrap();
+        assert_eq!(bytes, [0xa9, 0x12, 0x60, 0xc8, 0xe9, 0xa1, 0x00]);
+
+        assert!(parse_deposit("").is_err());
+        assert!(parse_deposit("100").is_err());
+        assert!(parse_deposit("\"oops").is_err());
+    }
+
+    #[test]
+    fn stepping_commands() {
+        let parse = |s: &str| s.parse::<Command>().unwrap();
+        assert!(matches!(parse("step"), Command::Step { count: 1 }));
+        assert!(matches!(parse("s 10"), Command::Step { count: 10 }));
+        assert!(matches!(
+            parse("until $0310"),
+            Command::Until {
+                addr: Location::Addr(0x310)
+            }
+        ));
+        assert!(matches!(
+            parse("u loop"),
+            Command::Until { addr: Location::Symbol(name) } if name == "loop"
+        ));
+        assert!(matches!(
+            parse("goto 300"),
+            Command::Goto {
+                addr: Location::Addr(0x300)
+            }
+        ));
+        assert!(matches!(
+            parse("call init_screen"),
+            Command::Call { addr: Location::Symbol(name) } if name == "init_screen"
+        ));
+
+        for bad in ["step x", "step 1 2", "until", "goto 1 2", "call foo!"] {
+            assert!(bad.parse::<Command>().is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn trace_commands() {
+        let parse = |s: &str| s.parse::<Command>().unwrap();
+        assert!(matches!(
+            parse("trace out.log 800.bfff symbols"),
+            Command::StartTrace {
+                path: Some(path),
+                range: Some(range),
+                symbols: true,
+            } if path == "out.log" && range == (0x800..=0xbfff)
+        ));
+        assert!(matches!(
+            parse("trace on"),
+            Command::StartTrace {
+                path: None,
+                range: None,
+                symbols: false,
+            }
+        ));
+        // A file called `symbols`.
+        assert!(matches!(
+            parse("trace symbols"),
+            Command::StartTrace {
+                path: Some(path),
+                symbols: false,
+                ..
+            } if path == "symbols"
+        ));
+        assert!(matches!(parse("trace off"), Command::StopTrace));
+        assert!("trace off symbols".parse::<Command>().is_err());
+    }
+
+    /// Counts X down to 0, recursing each time.
+    const RECURSIVE: &[u8] = &[
+        0xa2, 0x03, //       $0300  ldx #$03
+        0x20, 0x08, 0x03, // $0302  jsr $0308
+        0x4c, 0x05, 0x03, // $0305  jmp $0305
+        0xca, //             $0308  dex
+        0xf0, 0x06, //       $0309  beq $0311
+        0x20, 0x08, 0x03, // $030b  jsr $0308
+        0x86, 0x10, //       $030e  stx $10
+        0xea, //             $0310  nop
+        0x60, //             $0311  rts
+    ];
+
+    fn run(emu: &mut Emulator, command: &str) {
+        emu.control(command.parse().unwrap());
+    }
+
+    fn run_until_halted(emu: &mut Emulator) {
+        for _ in 0..1000 {
+            if emu.halted {
+                return;
+            }
+            emu.sim_1000_instrs();
+        }
+        panic!("didn't halt");
+    }
+
+    /// Runs until the given breakpoint, and then clears it.
+    fn run_to(addr: &str) -> Emulator {
+        let mut emu = Emulator::new(RECURSIVE, 0x300, 0x300, vec![]);
+        run(&mut emu, &format!("break {addr}"));
+        run_until_halted(&mut emu);
+        run(&mut emu, &format!("break {addr}"));
+        emu
+    }
+
+    fn sp(emu: &Emulator) -> u8 {
+        emu.cpu.register(Register::Sp) as u8
+    }
+
+    #[test]
+    fn next_steps_over_jsr() {
+        let mut emu = run_to("$0302");
+        let sp_before = sp(&emu);
+
+        run(&mut emu, "next");
+        run_until_halted(&mut emu);
+        assert_eq!(emu.cpu.pc(), 0x305);
+        assert_eq!(sp(&emu), sp_before);
+        assert_eq!(emu.cpu.register(Register::X), 0);
+    }
+
+    #[test]
+    fn next_skips_recursive_returns() {
+        // One call deep. The call we're stepping over returns to $030e too,
+        // but with the stack one call deeper.
+        let mut emu = run_to("$030b");
+        let sp_before = sp(&emu);
+
+        run(&mut emu, "next");
+        run_until_halted(&mut emu);
+        assert_eq!(emu.cpu.pc(), 0x30e);
+        assert_eq!(sp(&emu), sp_before);
+    }
+
+    #[test]
+    fn breakpoint_cancels_next() {
+        let mut emu = run_to("$0302");
+        run(&mut emu, "break $030b");
+
+        run(&mut emu, "next");
+        run_until_halted(&mut emu);
+        assert_eq!(emu.cpu.pc(), 0x30b);
+        assert!(emu.stop_at.is_none());
+    }
+
+    #[test]
+    fn watchpoint_cancels_next() {
+        let mut emu = run_to("$0302");
+        run(&mut emu, "watch $10");
+
+        run(&mut emu, "next");
+        run_until_halted(&mut emu);
+        assert_eq!(emu.cpu.pc(), 0x310);
+        assert!(emu.stop_at.is_none());
+    }
+
+    #[test]
+    fn watchpoint_on_jsr_cancels_next() {
+        let mut emu = run_to("$0302");
+        // Where the JSR pushes its return address.
+        let top = 0x100 + sp(&emu) as u16;
+        run(&mut emu, &format!("watch ${top:04x}"));
+
+        run(&mut emu, "next");
+        assert!(emu.halted);
+        assert_eq!(emu.cpu.pc(), 0x308);
+        assert!(emu.stop_at.is_none());
+    }
+
+    #[test]
+    fn watchpoint_on_first_instr_cancels_until() {
+        let mut emu = run_to("$0302");
+        let top = 0x100 + sp(&emu) as u16;
+        run(&mut emu, &format!("watch ${top:04x}"));
+
+        run(&mut emu, "until $0305");
+        assert!(emu.halted);
+        assert_eq!(emu.cpu.pc(), 0x308);
+        assert!(emu.stop_at.is_none());
+    }
+}
@@ -0,0 +1,393 @@
+//! Expressions, for conditional breakpoints and `print`.
+//!
+//! * Numbers: `$8d` or `0x8d` (hex), `141` (decimal)
+//! * Registers: `a`, `x`, `y`, `sp`, `pc`, `p`
+//! * Flags (0 or 1): `c`, `z`, `i`, `d`, `b`, `v`, `n`
+//! * Memory: `[$06]` is the byte at $06, and `w[$06]` is the (little-endian)
+//!   word at $06 and $07. A bare address in parentheses, like `($06)`, is also
+//!   the byte there; otherwise parentheses just group.
+//! * Arithmetic: `+`, `-`, `&`, `|`, `^`
+//! * Comparisons: `==`, `!=`, `<`, `<=`, `>`, `>=`
+//! * Boolean operators: `and`/`&&`, `or`/`||`, `not`/`!`
+//!
+//! E.g. `x > $10 and [$06] == 0`.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::{
+    cpu::{flags::Flag, Register},
+    Emulator,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Num(i64),
+    Register(Register),
+    Flag(Flag),
+    Byte(Box<Expr>),
+    Word(Box<Expr>),
+    Not(Box<Expr>),
+    BinOp(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+impl BinOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Or => "or",
+            BinOp::And => "and",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::BitOr => "|",
+            BinOp::BitXor => "^",
+            BinOp::BitAnd => "&",
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+        }
+    }
+}
+
+/// Lowest to highest. (`not` goes in between `and` and the comparisons.)
+const PRECEDENCE: &[&[BinOp]] = &[
+    &[BinOp::Or],
+    &[BinOp::And],
+    &[
+        BinOp::Eq,
+        BinOp::Ne,
+        BinOp::Lt,
+        BinOp::Le,
+        BinOp::Gt,
+        BinOp::Ge,
+    ],
+    &[BinOp::BitOr],
+    &[BinOp::BitXor],
+    &[BinOp::BitAnd],
+    &[BinOp::Add, BinOp::Sub],
+];
+const NOT_LEVEL: usize = 2;
+
+const REGISTERS: &[(&str, Register)] = &[
+    ("a", Register::A),
+    ("x", Register::X),
+    ("y", Register::Y),
+    ("sp", Register::Sp),
+    ("pc", Register::Pc),
+    ("p", Register::P),
+];
+
+const FLAGS: &[(&str, Flag)] = &[
+    ("c", Flag::Carry),
+    ("z", Flag::Zero),
+    ("i", Flag::Interrupt),
+    ("d", Flag::Decimal),
+    ("b", Flag::Break),
+    ("v", Flag::Overflow),
+    ("n", Flag::Negative),
+];
+
+impl Expr {
+    pub fn eval(&self, emu: &Emulator) -> Result<i64> {
+        let peek = |addr: i64| -> Result<i64> {
+            let addr = u16::try_from(addr)
+                .ok()
+                .with_context(|| format!("not an address: {addr}"))?;
+            let value = emu
+                .mem
+                .peek(addr)
+                .with_context(|| format!("can't peek at I/O: ${addr:04x}"))?;
+            Ok(value as i64)
+        };
+
+        Ok(match self {
+            Expr::Num(n) => *n,
+            Expr::Register(r) => emu.cpu.register(*r) as i64,
+            Expr::Flag(f) => emu.cpu.flag(*f) as i64,
+            Expr::Byte(addr) => peek(addr.eval(emu)?)?,
+            Expr::Word(addr) => {
+                let addr = addr.eval(emu)?;
+                peek(addr)? | peek(addr + 1)? << 8
+            }
+            Expr::Not(e) => (e.eval(emu)? == 0) as i64,
+            // (Short-circuit, so e.g. `x < $10 and [$1000 + x] == 0` doesn't
+            // have to be valid when x is big.)
+            Expr::BinOp(BinOp::And, l, r) => (l.eval(emu)? != 0 && r.eval(emu)? != 0) as i64,
+            Expr::BinOp(BinOp::Or, l, r) => (l.eval(emu)? != 0 || r.eval(emu)? != 0) as i64,
+            Expr::BinOp(op, l, r) => {
+                let (l, r) = (l.eval(emu)?, r.eval(emu)?);
+                match op {
+                    BinOp::Eq => (l == r) as i64,
+                    BinOp::Ne => (l != r) as i64,
+                    BinOp::Lt => (l < r) as i64,
+                    BinOp::Le => (l <= r) as i64,
+                    BinOp::Gt => (l > r) as i64,
+                    BinOp::Ge => (l >= r) as i64,
+                    BinOp::BitOr => l | r,
+                    BinOp::BitXor => l ^ r,
+                    BinOp::BitAnd => l & r,
+                    BinOp::Add => l.wrapping_add(r),
+                    BinOp::Sub => l.wrapping_sub(r),
+                    BinOp::And | BinOp::Or => unreachable!(),
+                }
+            }
+        })
+    }
+}
+
+impl FromStr for Expr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.binary(0)?;
+        if let Some(token) = parser.peek() {
+            bail!("unexpected {token:?} in expression");
+        }
+        Ok(expr)
+    }
+}
+
+impl fmt::Display for Expr {
+    /// Fully parenthesized, so there's no question about precedence.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Num(n) if *n >= 0 => write!(f, "${n:x}"),
+            Expr::Num(n) => write!(f, "{n}"),
+            Expr::Register(r) => {
+                let (name, _) = REGISTERS.iter().find(|(_, reg)| reg == r).unwrap();
+                write!(f, "{name}")
+            }
+            Expr::Flag(fl) => {
+                let (name, _) = FLAGS.iter().find(|(_, flag)| flag == fl).unwrap();
+                write!(f, "{name}")
+            }
+            Expr::Byte(addr) => write!(f, "[{addr}]"),
+            Expr::Word(addr) => write!(f, "w[{addr}]"),
+            Expr::Not(e) => write!(f, "not {e}"),
+            Expr::BinOp(op, l, r) => write!(f, "({l} {} {r})", op.symbol()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    /// Operators and brackets.
+    Punct(&'static str),
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    // Longest first, so e.g. `<=` doesn't come out as `<` then `=`.
+    const PUNCT: &[&str] = &[
+        "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "&", "|", "^", "+", "-", "(", ")", "[",
+        "]",
+    ];
+
+    let mut tokens = vec![];
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+
+        if let Some(p) = PUNCT.iter().find(|p| rest.starts_with(**p)) {
+            tokens.push(Token::Punct(p));
+            rest = &rest[p.len()..];
+        } else if c == '$' || rest.starts_with("0x") {
+            let digits = rest.strip_prefix('$').unwrap_or_else(|| &rest[2..]);
+            let len = digits
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .unwrap_or(digits.len());
+            ensure!(len > 0, "expected hex digits after {:?}", &rest[..1]);
+            tokens.push(Token::Num(i64::from_str_radix(&digits[..len], 16)?));
+            rest = &digits[len..];
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            tokens.push(Token::Num(rest[..len].parse()?));
+            rest = &rest[len..];
+        } else if c.is_ascii_alphabetic() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..len].to_ascii_lowercase()));
+            rest = &rest[len..];
+        } else {
+            bail!("unexpected {c:?} in expression");
+        }
+
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .context("unexpected end of expression")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<()> {
+        match self.next()? {
+            Token::Punct(p) if p == punct => Ok(()),
+            token => bail!("expected {punct:?}, got {token:?}"),
+        }
+    }
+
+    /// The binary operator that comes next, if it's one of `ops`.
+    fn binop(&mut self, ops: &[BinOp]) -> Option<BinOp> {
+        let op = match self.peek()? {
+            Token::Ident(word) if word == "or" => BinOp::Or,
+            Token::Ident(word) if word == "and" => BinOp::And,
+            Token::Punct("||") => BinOp::Or,
+            Token::Punct("&&") => BinOp::And,
+            Token::Punct(p) => *ops.iter().find(|op| op.symbol() == *p)?,
+            _ => return None,
+        };
+        if !ops.contains(&op) {
+            return None;
+        }
+        self.pos += 1;
+        Some(op)
+    }
+
+    /// Parse operators at this level of precedence (and higher).
+    fn binary(&mut self, level: usize) -> Result<Expr> {
+        if level == PRECEDENCE.len() {
+            return self.primary();
+        }
+        if level == NOT_LEVEL && self.is_not() {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.binary(level)?)));
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(op) = self.binop(PRECEDENCE[level]) {
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::BinOp(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn is_not(&self) -> bool {
+        match self.peek() {
+            Some(Token::Punct("!")) => true,
+            Some(Token::Ident(word)) => word == "not",
+            _ => false,
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        match self.next()? {
+            Token::Num(n) => Ok(Expr::Num(n)),
+            Token::Punct("(") => {
+                let e = self.binary(0)?;
+                self.expect(")")?;
+                // There's no point grouping a single number, so read it like
+                // an indirect operand. E.g. `($06) == 0`.
+                match e {
+                    Expr::Num(_) => Ok(Expr::Byte(Box::new(e))),
+                    e => Ok(e),
+                }
+            }
+            Token::Punct("[") => {
+                let e = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Byte(Box::new(e)))
+            }
+            Token::Ident(word) if word == "w" && self.peek() == Some(&Token::Punct("[")) => {
+                self.pos += 1;
+                let e = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Word(Box::new(e)))
+            }
+            // `!` and `not` also work on a single term, e.g. `x == 0 and !c`.
+            Token::Punct("!") => Ok(Expr::Not(Box::new(self.primary()?))),
+            Token::Ident(word) if word == "not" => Ok(Expr::Not(Box::new(self.primary()?))),
+            Token::Ident(word) => {
+                if let Some((_, r)) = REGISTERS.iter().find(|(name, _)| *name == word) {
+                    return Ok(Expr::Register(*r));
+                }
+                if let Some((_, f)) = FLAGS.iter().find(|(name, _)| *name == word) {
+                    return Ok(Expr::Flag(*f));
+                }
+                bail!("unknown name in expression: {word:?}")
+            }
+            token => bail!("unexpected {token:?} in expression"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("a == $8d", "(a == $8d)")]
+    #[test_case("x > 0x10 and [$06] == 0", "((x > $10) and ([$6] == $0))")]
+    #[test_case(
+        "a == 1 or x == 2 && y == 3",
+        "((a == $1) or ((x == $2) and (y == $3)))"
+    )]
+    #[test_case("not c and z", "(not c and z)")]
+    #[test_case("!c && z", "(not c and z)")]
+    #[test_case("w[$06] + y - 1 & $ff", "(((w[$6] + y) - $1) & $ff)")]
+    #[test_case("PC >= $C000", "(pc >= $c000)")]
+    #[test_case("X > $10 and ($06) == 0", "((x > $10) and ([$6] == $0))")]
+    #[test_case(
+        "(a == 1 or a == 2) and (x + 1) == 3",
+        "(((a == $1) or (a == $2)) and ((x + $1) == $3))"
+    )]
+    fn parse(s: &str, expected: &str) {
+        let expr: Expr = s.parse().unwrap();
+        assert_eq!(expr.to_string(), expected);
+        // And it parses back the same.
+        assert_eq!(expected.parse::<Expr>().unwrap(), expr);
+    }
+
+    #[test_case("a ==" ; "missing operand")]
+    #[test_case("[$06" ; "missing bracket")]
+    #[test_case("q == 1" ; "unknown name")]
+    #[test_case("a == $" ; "missing digits")]
+    #[test_case("1 2" ; "trailing tokens")]
+    fn parse_errors(s: &str) {
+        assert!(s.parse::<Expr>().is_err());
+    }
+}
@@ -4,4 +4,285 @@
 pub mod color;
 pub mod gr;
 pub mod hgr;
+pub mod ntsc;
 pub mod text;
+
+use std::{ops::Range, str::FromStr};
+
+use anyhow::{bail, Result};
+use color::Color;
+
+/// Width of a scanline, in 14 MHz "dots". This is twice the number of hi-res
+/// pixels, since each hi-res pixel lasts for 2 ticks of the 14 MHz clock.
+pub const W: usize = 2 * hgr::W;
+pub const H: usize = hgr::H;
+
+/// A whole screen's worth of pixels (`W` by `H`), row by row. Each pixel is
+/// packed as `0x00rrggbb`.
+pub type Frame = Vec<u32>;
+
+/// What kind of monitor is hooked up to the computer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Monitor {
+    /// Color monitor, hooked up to the composite video output. Decodes the
+    /// signal like an NTSC TV would, including the color fringing at the edges
+    /// of shapes.
+    #[default]
+    Composite,
+    /// Idealized colors, like you'd get from an "RGB card". No fringing.
+    Rgb,
+
+    /// Monochrome monitors. Every dot is either lit or not; there's no color
+    /// decoding at all.
+    White,
+    Green,
+    Amber,
+}
+
+impl Monitor {
+    pub fn next(self) -> Self {
+        match self {
+            Monitor::Composite => Monitor::Rgb,
+            Monitor::Rgb => Monitor::White,
+            Monitor::White => Monitor::Green,
+            Monitor::Green => Monitor::Amber,
+            Monitor::Amber => Monitor::Composite,
+        }
+    }
+
+    /// The color of a fully-lit dot, on a monochrome monitor.
+    fn phosphor(self) -> Option<[u8; 3]> {
+        match self {
+            Monitor::Composite | Monitor::Rgb => None,
+            Monitor::White => Some([0xff, 0xff, 0xff]),
+            Monitor::Green => Some([0x33, 0xff, 0x66]),
+            Monitor::Amber => Some([0xff, 0xb0, 0x00]),
+        }
+    }
+
+    /// What each of the 16 colors looks like on this monitor, packed as
+    /// `0x00rrggbb`.
+    pub fn palette(self) -> [u32; 16] {
+        std::array::from_fn(|i| pack_rgb(self.rgb(Color::from_nibble(i as u8))))
+    }
+
+    /// What the color actually looks like on this monitor.
+    pub fn rgb(self, color: Color) -> [u8; 3] {
+        match self.phosphor() {
+            Some(phosphor) => {
+                let brightness = color.luma();
+                phosphor.map(|x| (x as f64 * brightness).round() as u8)
+            }
+            None => color.rgb(),
+        }
+    }
+}
+
+impl FromStr for Monitor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let monitor = match s {
+            "composite" => Monitor::Composite,
+            "rgb" => Monitor::Rgb,
+            "white" => Monitor::White,
+            "green" => Monitor::Green,
+            "amber" => Monitor::Amber,
+            _ => bail!("expected one of: composite, rgb, white, green, amber"),
+        };
+        Ok(monitor)
+    }
+}
+
+/// The soft switches that affect what gets displayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mode {
+    pub text: bool,
+    pub mixed: bool,
+    pub hires: bool,
+    /// Note that this should already account for 80STORE. (When 80STORE is
+    /// set, PAGE2 selects aux memory instead of the second display page.)
+    pub page2: bool,
+    pub col80: bool,
+    pub dhires: bool,
+}
+
+/// The parts of RAM that can be displayed: either main or aux memory,
+/// $0000..$c000.
+pub struct VideoRam<'a> {
+    pub main: &'a [u8],
+    pub aux: &'a [u8],
+}
+
+/// Everything needed to draw one scanline, copied out of memory at the moment
+/// the beam passes by.
+#[derive(Debug, Clone, Copy)]
+pub struct Line {
+    pub mode: Mode,
+    /// The bytes that get displayed on this line.
+    pub main: [u8; gr::W],
+    /// Only used in 80-column modes.
+    pub aux: [u8; gr::W],
+}
+
+/// Which kind of graphics (or text) is shown on a given line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Text,
+    // todo: double lo-res
+    Gr,
+    Hgr,
+    DoubleHgr,
+}
+
+impl Mode {
+    pub fn source(self, y: usize) -> Source {
+        if self.text || (self.mixed && y >= 20 * text::CELL_H) {
+            Source::Text
+        } else if self.hires && self.dhires && self.col80 {
+            Source::DoubleHgr
+        } else if self.hires {
+            Source::Hgr
+        } else {
+            Source::Gr
+        }
+    }
+}
+
+/// Render the whole screen, as it is right now.
+pub fn frame(ram: &VideoRam, mode: Mode, monitor: Monitor) -> Frame {
+    let palette = monitor.palette();
+    let mut frame = vec![0; W * H];
+    for y in 0..H {
+        draw(&mut frame, &latch(ram, mode, y), monitor, &palette, y);
+    }
+    frame
+}
+
+/// Render a single scanline into the frame.
+pub fn draw(frame: &mut [u32], line: &Line, monitor: Monitor, palette: &[u32; 16], y: usize) {
+    let colors = render(line, monitor, y);
+    for (pixel, color) in frame[y * W..][..W].iter_mut().zip(colors) {
+        *pixel = palette[color as usize];
+    }
+}
+
+/// Grab the bytes for a single scanline.
+pub fn latch(ram: &VideoRam, mode: Mode, y: usize) -> Line {
+    let range = source_range(mode, y);
+    Line {
+        mode,
+        main: ram.main[range.clone()].try_into().unwrap(),
+        aux: ram.aux[range].try_into().unwrap(),
+    }
+}
+
+/// The bytes on the current text page, one row at a time. (Whether or not
+/// we're actually in text mode.)
+///
+/// In 80-column mode, each row alternates between aux and main bytes, in the
+/// order they're displayed.
+pub fn text_page(ram: &VideoRam, mode: Mode) -> Vec<Vec<u8>> {
+    let mode = Mode { text: true, ..mode };
+    (0..H)
+        .step_by(text::CELL_H)
+        .map(|y| {
+            let line = latch(ram, mode, y);
+            text::row_bytes(&line.main, &line.aux, mode.col80)
+        })
+        .collect()
+}
+
+/// Where in memory (main or aux) the bytes for a scanline come from.
+pub fn source_range(mode: Mode, y: usize) -> Range<usize> {
+    match mode.source(y) {
+        Source::Text | Source::Gr => {
+            let base = if mode.page2 { 0x800 } else { 0x400 };
+            let offset = gr::row_offset(y / text::CELL_H);
+            base + offset..base + offset + gr::W
+        }
+        Source::Hgr | Source::DoubleHgr => {
+            let base = if mode.page2 { 0x4000 } else { 0x2000 };
+            let offset = hgr::line_offset(y);
+            base + offset..base + offset + gr::W
+        }
+    }
+}
+
+/// Render a single row of dots.
+pub fn render(line: &Line, monitor: Monitor, y: usize) -> [Color; W] {
+    let source = line.mode.source(y);
+
+    let signal = match source {
+        Source::Text => text::signal(&line.main, &line.aux, line.mode.col80, y),
+        Source::Gr => gr::signal(&line.main, y),
+        Source::Hgr => hgr::signal(&line.main),
+        Source::DoubleHgr => hgr::double_signal(&line.main, &line.aux),
+    };
+
+    match monitor {
+        Monitor::White | Monitor::Green | Monitor::Amber => mono(&signal),
+
+        // The //e turns off the color burst in (non-mixed) text mode, so a
+        // color monitor shows crisp white text.
+        Monitor::Composite if line.mode.text => mono(&signal),
+        Monitor::Composite => ntsc::decode(&signal),
+
+        Monitor::Rgb => match source {
+            Source::Text => mono(&signal),
+            Source::Gr => gr::rgb(&line.main, y),
+            Source::Hgr => hgr::rgb(&line.main),
+            Source::DoubleHgr => hgr::double_rgb(&signal),
+        },
+    }
+}
+
+fn mono(signal: &[bool; W]) -> [Color; W] {
+    signal.map(|bit| if bit { Color::White } else { Color::Black })
+}
+
+fn pack_rgb([r, g, b]: [u8; 3]) -> u32 {
+    let r = r as u32;
+    let g = g as u32;
+    let b = b as u32;
+    r << 16 | g << 8 | b
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(false, "HELLO"; "40 columns")]
+    #[test_case(true, "xHxExLxLxO"; "80 columns")]
+    fn text_page_rows(col80: bool, expected: &str) {
+        let mut main = vec![0xa0; 0xc000];
+        let mut aux = vec![0xa0; 0xc000];
+
+        // The last row, which is also shown in mixed mode.
+        let addr = 0x400 + gr::row_offset(23);
+        for (i, b) in "HELLO".bytes().enumerate() {
+            main[addr + i] = b | 0x80;
+            aux[addr + i] = b'x' | 0x80;
+        }
+
+        let mode = Mode {
+            text: false,
+            mixed: true,
+            hires: true,
+            page2: false,
+            col80,
+            dhires: false,
+        };
+        let ram = VideoRam {
+            main: &main,
+            aux: &aux,
+        };
+        let rows = text_page(&ram, mode);
+
+        assert_eq!(rows.len(), 24);
+        let row: String = rows[23].iter().map(|&b| text::decode(b).0).collect();
+        assert_eq!(row.trim_end(), expected);
+    }
+}
@@ -37,6 +37,11 @@ impl Color {
         math::yuv_to_rgb(yuv)
     }
 
+    /// Brightness, in the range [0, 1].
+    pub fn luma(self) -> f64 {
+        self.yuv()[0]
+    }
+
     fn yuv(self) -> [f64; 3] {
         let x = (2_f64).sqrt() / PI;
 
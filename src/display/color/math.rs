@@ -17,7 +17,7 @@ pub fn yuv_to_rgb(yuv: Vec3) -> [u8; 3] {
 fn f64_to_u8(x: f64) -> u8 {
     assert!(!x.is_nan());
     let scaled = x.clamp(0., 1.) * 255.;
-    debug_assert!(0. <= scaled && scaled <= 255.);
+    debug_assert!((0. ..=255.).contains(&scaled));
     scaled.round() as u8
 }
 
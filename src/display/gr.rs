@@ -1,5 +1,5 @@
 use super::color::Color;
-use crate::display::hgr;
+use crate::display;
 
 pub const W: usize = 40;
 pub const H: usize = 48;
@@ -7,43 +7,49 @@ pub const H: usize = 48;
 pub const BLOCK_W: usize = 7;
 pub const BLOCK_H: usize = 4;
 
-pub fn dots(page: &[u8]) -> Vec<Vec<Color>> {
-    let blocks = color_grid(page);
-
-    let mut out = vec![vec![Color::Black; hgr::W]; hgr::H];
-    for y in 0..hgr::H {
-        for x in 0..hgr::W {
-            out[y][x] = blocks[y / BLOCK_H][x / BLOCK_W];
-        }
+/// The signal for one scanline.
+///
+/// Each block's color is a 4-bit pattern, repeated over and over. A color
+/// monitor interprets the pattern as a color; see `ntsc::decode`.
+pub fn signal(bytes: &[u8; W], y: usize) -> [bool; display::W] {
+    let row = color_row(bytes, y / BLOCK_H);
+
+    let mut out = [false; display::W];
+    for (i, bit) in out.iter_mut().enumerate() {
+        let nibble = row[i / (2 * BLOCK_W)] as u8;
+        *bit = nibble & 1 << (i % 4) != 0;
     }
     out
 }
 
-fn color_grid(page: &[u8]) -> Vec<Vec<Color>> {
-    let bytes = unscramble_bytes(page);
-
-    let mut out = vec![vec![Color::Black; W]; H];
-    for y in 0..H / 2 {
-        for x in 0..W {
-            let b = bytes[y][x];
-            let top = b & 0xf;
-            let bot = b >> 4;
-            out[y * 2][x] = Color::from_nibble(top);
-            out[y * 2 + 1][x] = Color::from_nibble(bot);
-        }
+/// Idealized colors for one scanline.
+pub fn rgb(bytes: &[u8; W], y: usize) -> [Color; display::W] {
+    let row = color_row(bytes, y / BLOCK_H);
+
+    let mut out = [Color::Black; display::W];
+    for (i, color) in out.iter_mut().enumerate() {
+        *color = row[i / (2 * BLOCK_W)];
     }
     out
 }
 
-pub(super) fn unscramble_bytes(page: &[u8]) -> Vec<Vec<u8>> {
-    assert_eq!(page.len(), 0x400); // 1 KiB
-
-    let mut out = vec![vec![0u8; W]; H / 2];
-    for i in 0..8 {
-        for j in 0..3 {
-            let row = &page[i * 0x80 + j * W..][..W];
-            out[j * 8 + i].copy_from_slice(row);
-        }
+/// One row of blocks. (Each byte holds 2 blocks, one above the other.)
+fn color_row(bytes: &[u8; W], block_y: usize) -> [Color; W] {
+    let mut out = [Color::Black; W];
+    for x in 0..W {
+        let b = bytes[x];
+        let nibble = if block_y.is_multiple_of(2) { b & 0xf } else { b >> 4 };
+        out[x] = Color::from_nibble(nibble);
     }
     out
 }
+
+/// One row of text (or two rows of lo-res blocks) is stored as a contiguous
+/// run of 40 bytes, but the rows are scrambled around within the 1 KiB page.
+pub fn row_offset(y: usize) -> usize {
+    assert!(y < H / 2);
+
+    let i = y % 8;
+    let j = y / 8;
+    i * 0x80 + j * W
+}
@@ -1,38 +1,63 @@
 mod memory_mapping;
 
 use itertools::Itertools;
+pub use memory_mapping::line_offset;
 use memory_mapping::Byte;
 
 use super::color::Color;
+use crate::display::{self, gr};
 
 pub const W: usize = 280;
 pub const H: usize = 192;
 
-/// Black & white display.
-pub fn dots_bw(mem: &[u8]) -> Vec<Vec<Color>> {
-    memory_mapping::unscramble(mem)
-        .into_iter()
-        .map(|row| row.into_iter().flat_map(|byte| byte.bits.map(bw)).collect())
-        .collect()
-}
+/// The signal for one scanline.
+///
+/// Each dot lasts for 2 ticks of the 14 MHz clock. If a byte's flag bit is
+/// set, its dots are delayed by one tick (half a dot). During that first tick,
+/// the last dot of the previous byte is held a bit longer.
+pub fn signal(bytes: &[u8; gr::W]) -> [bool; display::W] {
+    let mut out = [false; display::W];
+    let mut prev_dot = false;
+
+    for (i, byte) in bytes.map(Byte::new).into_iter().enumerate() {
+        let mut pos = i * 14;
+        if byte.flag_bit {
+            out[pos] = prev_dot;
+            pos += 1;
+        }
 
-fn bw(bit: bool) -> Color {
-    if bit {
-        Color::White
-    } else {
-        Color::Black
+        for dot in byte.bits {
+            for _ in 0..2 {
+                // The very last tick of a delayed byte spills into the next
+                // byte (or off the end of the screen).
+                if pos < display::W {
+                    out[pos] = dot;
+                }
+                pos += 1;
+            }
+        }
+
+        prev_dot = byte.bits[6];
     }
+
+    out
 }
 
-pub fn dots_color(mem: &[u8]) -> Vec<Vec<Color>> {
-    memory_mapping::unscramble(mem)
-        .into_iter()
-        .map(|row| {
-            row.chunks(2)
-                .flat_map(|word| dots_14(word.try_into().unwrap()))
-                .collect()
-        })
-        .collect()
+/// Idealized colors for one scanline.
+pub fn rgb(bytes: &[u8; gr::W]) -> [Color; display::W] {
+    let row = bytes.map(Byte::new);
+
+    let dots = row
+        .chunks(2)
+        .flat_map(|word| dots_14(word.try_into().unwrap()))
+        .collect_vec();
+
+    // Each color lasts for 2 ticks of the 14 MHz clock.
+    let mut out = [Color::Black; display::W];
+    for (i, color) in out.iter_mut().enumerate() {
+        *color = dots[i / 2];
+    }
+    out
 }
 
 fn dots_14(word: [Byte; 2]) -> [Color; 14] {
@@ -78,3 +103,35 @@ fn dots_2((bits, flag): ([bool; 2], bool)) -> [Color; 2] {
     };
     [c, c]
 }
+
+/// The signal for one scanline of double hi-res.
+///
+/// Each column is an aux byte followed by a main byte, and each bit lasts for
+/// a single tick of the 14 MHz clock. The flag bits are ignored.
+pub fn double_signal(main: &[u8; gr::W], aux: &[u8; gr::W]) -> [bool; display::W] {
+    let bytes = aux.iter().interleave(main);
+
+    let mut out = [false; display::W];
+    for (i, byte) in bytes.enumerate() {
+        for j in 0..7 {
+            out[i * 7 + j] = byte & 1 << j != 0;
+        }
+    }
+    out
+}
+
+/// Idealized colors for double hi-res: each group of 4 ticks is a single
+/// color, taken straight from the bit pattern.
+pub fn double_rgb(signal: &[bool; display::W]) -> [Color; display::W] {
+    let mut out = [Color::Black; display::W];
+    for (i, group) in signal.chunks_exact(4).enumerate() {
+        let mut nibble = 0;
+        for (j, &bit) in group.iter().enumerate() {
+            if bit {
+                nibble |= 1 << j;
+            }
+        }
+        out[i * 4..][..4].fill(Color::from_nibble(nibble));
+    }
+    out
+}
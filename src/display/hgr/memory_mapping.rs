@@ -1,3 +1,5 @@
+use std::array;
+
 use crate::display::gr;
 
 // Note: we could make this a wrapper around a u8, if it ends up mattering for
@@ -10,58 +12,19 @@ pub struct Byte {
     pub bits: [bool; 7],
 }
 
-pub fn unscramble(page: &[u8]) -> Vec<Vec<Byte>> {
-    assert_eq!(page.len(), 0x2000);
-    let sheets = split_sheets(page);
-    let sheets = sheets.map(gr::unscramble_bytes);
-    let sheets = sheets.map(sheet_u8_to_byte);
-    weave_sheets(sheets)
-}
-
-fn split_sheets(page: &[u8]) -> [&[u8]; 8] {
-    let mut out = [[].as_slice(); 8];
-    for i in 0..8 {
-        out[i] = &page[i * 0x400..][..0x400];
-    }
-    out
-}
-
-fn sheet_u8_to_byte(sheet: Vec<Vec<u8>>) -> Vec<Vec<Byte>> {
-    sheet
-        .into_iter()
-        .map(|row| row.into_iter().map(Byte::new).collect())
-        .collect()
+/// Where the 40 bytes that make up one scanline are, within the page.
+///
+/// The page is split into 8 "sheets" of 1 KiB each. Each sheet is laid out
+/// like a page of text, and the sheets are woven together: the first scanline
+/// comes from sheet 0, the second from sheet 1, etc.
+pub fn line_offset(y: usize) -> usize {
+    y % 8 * 0x400 + gr::row_offset(y / 8)
 }
 
 impl Byte {
-    fn new(byte: u8) -> Self {
-        let mut bits = [false; 7];
-        for i in 0..7 {
-            let is_set = byte & 1 << i != 0;
-            bits[i] = is_set;
-        }
+    pub fn new(byte: u8) -> Self {
+        let bits = array::from_fn(|i| byte & 1 << i != 0);
         let flag_bit = byte & 0x80 != 0;
         Self { flag_bit, bits }
     }
 }
-
-fn weave_sheets(sheets: [Vec<Vec<Byte>>; 8]) -> Vec<Vec<Byte>> {
-    // Each sheet has the same number of rows.
-    let num_rows = sheets[0].len();
-    debug_assert!(sheets.iter().all(|sheet| sheet.len() == num_rows));
-
-    let mut sheets = sheets.map(IntoIterator::into_iter);
-
-    let mut out = vec![];
-    for _ in 0..num_rows {
-        for i in 0..8 {
-            let row = sheets[i].next().unwrap();
-            out.push(row);
-        }
-    }
-
-    // All done!
-    debug_assert!(sheets.iter_mut().all(|sheet| sheet.next().is_none()));
-
-    out
-}
@@ -0,0 +1,77 @@
+//! A simple model of how a composite color monitor decodes the Apple II's
+//! video signal.
+//!
+//! The signal is a stream of bits at 14 MHz, i.e. 4 bits per cycle of the
+//! 3.58 MHz color subcarrier. The monitor can't really tell individual bits
+//! apart; what it sees is the brightness and the phase of each 4-bit window.
+//! So at each point on the scanline, we look at the surrounding window and
+//! read it off as a 4-bit pattern. By design, the 16 possible patterns are
+//! exactly the 16 lo-res colors.
+//!
+//! This is what gives hi-res graphics their color fringing: e.g. the edge of
+//! a white shape sees a window that's only partially lit, which decodes as a
+//! color.
+
+use super::{color::Color, W};
+
+/// Where the window starts, relative to the dot being decoded.
+const WINDOW_OFFSET: usize = 1;
+
+pub fn decode(signal: &[bool; W]) -> [Color; W] {
+    let mut out = [Color::Black; W];
+    for (x, color) in out.iter_mut().enumerate() {
+        let start = x.saturating_sub(WINDOW_OFFSET);
+        let end = (start + 4).min(W);
+
+        let mut nibble = 0;
+        for (i, &bit) in signal.iter().enumerate().take(end).skip(start) {
+            // The phase of the color subcarrier is "absolute", i.e. it doesn't
+            // depend on where the window starts.
+            if bit {
+                nibble |= 1 << (i % 4);
+            }
+        }
+
+        *color = Color::from_nibble(nibble);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repeat(pattern: [bool; 4]) -> [bool; W] {
+        let mut out = [false; W];
+        for i in 0..W {
+            out[i] = pattern[i % 4];
+        }
+        out
+    }
+
+    #[test]
+    fn solid_colors() {
+        for nibble in 0..16 {
+            let pattern = [0, 1, 2, 3].map(|i| nibble & 1 << i != 0);
+            let colors = decode(&repeat(pattern));
+
+            // Skip the edges of the screen, where the window gets cut off.
+            let expected = Color::from_nibble(nibble);
+            assert!(colors[4..W - 4].iter().all(|&c| c == expected));
+        }
+    }
+
+    #[test]
+    fn white_has_fringes() {
+        let mut signal = [false; W];
+        signal[100..120].fill(true);
+        let colors = decode(&signal);
+
+        assert_eq!(colors[50], Color::Black);
+        assert_eq!(colors[110], Color::White);
+        assert_ne!(colors[99], Color::White);
+        assert_ne!(colors[99], Color::Black);
+        assert_ne!(colors[119], Color::White);
+        assert_ne!(colors[119], Color::Black);
+    }
+}
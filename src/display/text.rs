@@ -4,51 +4,84 @@ use core::fmt;
 
 use spritesheet::SPRITES;
 
-use super::{color::Color, hgr};
-use crate::display::gr::unscramble_bytes;
+use crate::display;
 
 pub const W: usize = 40;
-pub const H: usize = 24;
 
 pub const CELL_W: usize = 7;
 pub const CELL_H: usize = 8;
 
-pub fn dots(page: &[u8]) -> Vec<Vec<Color>> {
-    let cells = glyphs(page);
-
-    let mut out = vec![vec![Color::Black; hgr::W]; hgr::H];
-    for y in 0..H {
-        for x in 0..W {
-            draw(&mut out, x * CELL_W, y * CELL_H, cells[y][x]);
+/// The signal for one scanline.
+///
+/// In 40-column mode, each dot lasts for 2 ticks of the 14 MHz clock. In
+/// 80-column mode, each column is an aux glyph followed by a main glyph, and
+/// each dot lasts for a single tick.
+pub fn signal(main: &[u8; W], aux: &[u8; W], col80: bool, y: usize) -> [bool; display::W] {
+    let main = main.map(Glyph::from_byte);
+    let aux = aux.map(Glyph::from_byte);
+
+    let mut out = [false; display::W];
+    for x in 0..W {
+        let cell = &mut out[x * 2 * CELL_W..][..2 * CELL_W];
+
+        if col80 {
+            let (left, right) = cell.split_at_mut(CELL_W);
+            left.copy_from_slice(&aux[x].dots()[y % CELL_H]);
+            right.copy_from_slice(&main[x].dots()[y % CELL_H]);
+        } else {
+            let dots = main[x].dots()[y % CELL_H];
+            for dx in 0..CELL_W {
+                cell[dx * 2..][..2].fill(dots[dx]);
+            }
         }
     }
     out
 }
 
-fn glyphs(page: &[u8]) -> Vec<Vec<Glyph>> {
-    let bytes = unscramble_bytes(page);
-
-    let mut out = vec![vec![Glyph::Cursor; W]; H];
-    for y in 0..H {
-        for x in 0..W {
-            out[y][x] = Glyph::from_byte(bytes[y][x]);
+/// The bytes of one row of text, in the order they're displayed.
+pub fn row_bytes(main: &[u8; W], aux: &[u8; W], col80: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(2 * W);
+    for x in 0..W {
+        if col80 {
+            out.push(aux[x]);
         }
+        out.push(main[x]);
     }
     out
 }
 
-fn draw(dots: &mut Vec<Vec<Color>>, x: usize, y: usize, glyph: Glyph) {
-    let sprite = glyph.dots();
-    for dy in 0..CELL_H {
-        for dx in 0..CELL_W {
-            let color = if sprite[dy][dx] {
-                Color::White
-            } else {
-                Color::Black
-            };
-            dots[y + dy][x + dx] = color;
-        }
-    }
+/// How a character gets drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Attr {
+    #[default]
+    Normal,
+    Inverse,
+    Flash,
+}
+
+/// The character that a screen byte shows, in the primary character set.
+/// (We don't emulate ALTCHAR yet.)
+///
+/// Inverse and flashing text only have uppercase letters and symbols. Normal
+/// text has uppercase letters twice, in $80..$a0 and $c0..$e0.
+pub fn decode(b: u8) -> (char, Attr) {
+    let attr = match b {
+        0x00..=0x3f => Attr::Inverse,
+        0x40..=0x7f => Attr::Flash,
+        0x80..=0xff => Attr::Normal,
+    };
+
+    let ascii = match b {
+        0x00..=0x1f | 0x40..=0x5f | 0x80..=0x9f => b & 0x1f | 0x40,
+        0x20..=0x3f | 0x60..=0x7f | 0xa0..=0xbf => b & 0x1f | 0x20,
+        0xc0..=0xff => b & 0x7f,
+    };
+
+    let c = match ascii {
+        0x7f => '▒',
+        _ => ascii as char,
+    };
+    (c, attr)
 }
 
 // (can add inverse & blinking text at some point)
@@ -1,8 +1,12 @@
 use std::{
+    collections::HashMap,
     env,
     error::Error,
     fs::File,
     io::prelude::*,
+    num::NonZeroU32,
+    ops::Range,
+    path::PathBuf,
     rc::Rc,
     sync::{Arc, Mutex},
     thread,
@@ -11,25 +15,32 @@ use std::{
 
 use anyhow::{Context as _, Result};
 use itertools::Itertools;
-use softbuffer::{Context, SoftBufferError, Surface};
+use softbuffer::{Context, Rect, SoftBufferError, Surface};
 use winit::{
     application::ApplicationHandler,
     dpi::{PhysicalPosition, PhysicalSize},
     event::{ElementState, KeyEvent, WindowEvent},
     event_loop::{ActiveEventLoop, EventLoop, EventLoopClosed, OwnedDisplayHandle},
-    keyboard::{Key, NamedKey},
+    keyboard::{Key, KeyLocation, ModifiersState, NamedKey, PhysicalKey},
     window::{Window, WindowId},
 };
 
 use crate::{
     cpu::Cpu,
-    display::{color::Color, gr, hgr, text},
+    display::{self, hgr},
     memory::AddressSpace,
-    Emulator,
+    save_state, screenshot, Emulator,
 };
 
+mod effects;
+
+pub use effects::Effects;
+
 /// What is the side-length (in physical pixels) of an emulated pixel (i.e. a
 /// "dot of light" on the CRT display).
+///
+/// (`paint_surface` spreads each pair of dots over exactly 3 pixels, so this
+/// can't change without changing that too.)
 const SCALE: usize = 3;
 
 const DESIRED_WINDOW_SIZE: PhysicalSize<u32> =
@@ -44,16 +55,38 @@ pub struct Gui {
     occluded: bool,
     window_size: PhysicalSize<u32>,
     emu: Arc<Mutex<Emulator>>,
+    effects: Effects,
+    /// The previous frame, for the phosphor persistence effect.
+    afterglow: Vec<u32>,
+    /// The number of the frame we last painted, if it had one. See
+    /// `Emulator::draw_screen_since`.
+    painted: Option<u64>,
+
+    modifiers: ModifiersState,
+    /// The keys being held down, and what we sent to the emulator for each.
+    held_keys: HashMap<PhysicalKey, u8>,
+    /// Open Apple and Solid Apple.
+    apple_keys: [bool; 2],
+
+    /// Which save state slot F6 and F7 use (1 through 9).
+    slot: u32,
 }
 
 impl Gui {
-    pub fn new(emu: Arc<Mutex<Emulator>>) -> Self {
+    pub fn new(emu: Arc<Mutex<Emulator>>, effects: Effects) -> Self {
         Self {
             window: None,
             surface: None,
             occluded: false,
             window_size: DESIRED_WINDOW_SIZE,
             emu,
+            effects,
+            afterglow: vec![],
+            painted: None,
+            modifiers: ModifiersState::empty(),
+            held_keys: HashMap::new(),
+            apple_keys: [false; 2],
+            slot: 1,
         }
     }
 }
@@ -116,11 +149,9 @@ impl Gui {
         match event {
             WindowEvent::CloseRequested => event_loop.exit(),
 
-            WindowEvent::Occluded(occluded) => {
-                if self.occluded != occluded {
-                    self.occluded = occluded;
-                    window.request_redraw();
-                }
+            WindowEvent::Occluded(occluded) if self.occluded != occluded => {
+                self.occluded = occluded;
+                window.request_redraw();
             }
 
             WindowEvent::RedrawRequested if !self.occluded => self.redraw()?,
@@ -143,6 +174,8 @@ impl Gui {
                 is_synthetic: false,
                 ..
             } => self.key_event(event),
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers.state(),
+            WindowEvent::Focused(false) => self.release_all_keys(),
 
             _ => (),
         }
@@ -150,41 +183,182 @@ impl Gui {
         Ok(())
     }
 
-    fn key_event(&self, e: KeyEvent) {
-        // This mapping probably isn't 100% accurate, and we aren't handling
-        // modifiers very carefully. See the table on page 13 of the //e
-        // Technical Reference Manual for more ideas.
-        let ascii_code = match e.logical_key {
-            Key::Named(key) => {
-                match key {
-                    NamedKey::Backspace => 0x7f,
-                    NamedKey::ArrowLeft => 0x08,
-                    NamedKey::Tab => 0x09,
-                    NamedKey::ArrowDown => 0x0a,
-                    NamedKey::ArrowUp => 0x0b,
-                    NamedKey::Enter => 0x0d,
-                    NamedKey::ArrowRight => 0x15,
-                    NamedKey::Escape => 0x1b,
-                    NamedKey::Space => 0x20,
-                    NamedKey::Insert => todo!(), // (low-prio todo: use Insert as RESET?)
-                    _ => return,
+    fn key_event(&mut self, e: KeyEvent) {
+        let pressed = e.state.is_pressed();
+
+        // Emulator controls (not part of the Apple II keyboard).
+        if pressed && !e.repeat {
+            match e.logical_key {
+                Key::Named(NamedKey::F2) => {
+                    let mut emu = self.emu.lock().unwrap();
+                    let monitor = emu.monitor().next();
+                    emu.set_monitor(monitor);
+                    eprintln!("\nmonitor: {monitor:?}");
+                    return;
+                }
+                Key::Named(NamedKey::F3) => {
+                    self.effects.scanlines = !self.effects.scanlines;
+                    return;
+                }
+                Key::Named(NamedKey::F4) => {
+                    self.effects.persistence = !self.effects.persistence;
+                    self.afterglow.clear();
+                    return;
+                }
+                Key::Named(NamedKey::F5) => {
+                    self.paste();
+                    return;
+                }
+                Key::Named(NamedKey::F6) => {
+                    let path = save_state::slot_path(self.slot);
+                    match self.emu.lock().unwrap().save_state(&path) {
+                        Ok(()) => eprintln!("\nsaved {}", path.display()),
+                        Err(e) => eprintln!("\nfailed to save state: {e}"),
+                    }
+                    return;
+                }
+                Key::Named(NamedKey::F7) => {
+                    let path = save_state::slot_path(self.slot);
+                    match self.emu.lock().unwrap().load_state(&path) {
+                        Ok(()) => eprintln!("\nloaded {}", path.display()),
+                        Err(e) => eprintln!("\nfailed to load {}: {e}", path.display()),
+                    }
+                    return;
+                }
+                Key::Named(NamedKey::F8) => {
+                    self.slot = self.slot % 9 + 1;
+                    eprintln!("\nsave slot: {}", self.slot);
+                    return;
                 }
+                Key::Named(NamedKey::F9) => {
+                    // Go back about a second each time.
+                    if let Err(e) = self.emu.lock().unwrap().rewind_seconds(1.) {
+                        eprintln!("\nfailed to rewind: {e}");
+                    }
+                    // Start fresh, rather than guessing which keys were held
+                    // back then.
+                    self.release_all_keys();
+                    return;
+                }
+                Key::Named(NamedKey::F12) => {
+                    self.screenshot();
+                    return;
+                }
+                _ => (),
             }
-            Key::Character(key) if key.len() == 1 => key.as_bytes()[0],
-            _ => return,
+        }
+
+        let mut emu = self.emu.lock().unwrap();
+
+        // The Alt keys stand in for Open Apple and Solid Apple.
+        if let Key::Named(NamedKey::Alt | NamedKey::AltGraph) = e.logical_key {
+            match e.location {
+                KeyLocation::Left => self.apple_keys[0] = pressed,
+                _ => self.apple_keys[1] = pressed,
+            }
+            let [open, solid] = self.apple_keys;
+            emu.set_apple_keys(open, solid);
+            return;
+        }
+
+        // The emulated keyboard does its own auto-repeat.
+        if e.repeat {
+            return;
+        }
+
+        if !pressed {
+            if let Some(ascii_code) = self.held_keys.remove(&e.physical_key) {
+                emu.key_up(ascii_code);
+            }
+            return;
+        }
+
+        // Ctrl-Reset. (The //e doesn't reset unless you hold Ctrl.)
+        let ctrl = self.modifiers.control_key();
+        if let Key::Named(NamedKey::Insert | NamedKey::Pause) = e.logical_key {
+            if ctrl {
+                emu.reset();
+            }
+            return;
+        }
+
+        let Some(ascii_code) = ascii_code(&e.logical_key, ctrl) else {
+            return;
         };
+        // Remember what we sent, since the modifiers might be different by
+        // the time the key is released.
+        self.held_keys.insert(e.physical_key, ascii_code);
+        emu.key_down(ascii_code);
+    }
+
+    /// Let go of everything, e.g. when the window loses focus. (Otherwise
+    /// we'd never find out that the keys were released.)
+    fn release_all_keys(&mut self) {
+        self.held_keys.clear();
+        self.apple_keys = [false; 2];
+
+        let mut emu = self.emu.lock().unwrap();
+        emu.all_keys_up();
+        emu.set_apple_keys(false, false);
+    }
+}
 
-        if e.state.is_pressed() {
-            self.emu.lock().unwrap().key_down(ascii_code);
-        } else {
-            // todo: this is technically not a correct implementation, but I'm
-            // guessing it'll feel "good enough", for now.
-            self.emu.lock().unwrap().all_keys_up();
+/// See the table on page 13 of the //e Technical Reference Manual.
+fn ascii_code(key: &Key, ctrl: bool) -> Option<u8> {
+    let ascii_code = match key {
+        Key::Named(key) => match key {
+            NamedKey::Backspace => 0x7f,
+            NamedKey::ArrowLeft => 0x08,
+            NamedKey::Tab => 0x09,
+            NamedKey::ArrowDown => 0x0a,
+            NamedKey::ArrowUp => 0x0b,
+            NamedKey::Enter => 0x0d,
+            NamedKey::ArrowRight => 0x15,
+            NamedKey::Escape => 0x1b,
+            NamedKey::Space => 0x20,
+            _ => return None,
+        },
+        Key::Character(key) if key.len() == 1 && key.is_ascii() => key.as_bytes()[0],
+        _ => return None,
+    };
+
+    // Ctrl clears the top 2 bits of letters (and @[\]^_). Other keys are
+    // unaffected.
+    let upper = ascii_code.to_ascii_uppercase();
+    if ctrl && (b'@'..=b'_').contains(&upper) {
+        return Some(upper & 0x1f);
+    }
+    Some(ascii_code)
+}
+
+impl Gui {
+    /// Type whatever text is on the host's clipboard.
+    fn paste(&self) {
+        let text = arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text());
+        match text {
+            Ok(text) => self.emu.lock().unwrap().type_text(&text),
+            Err(e) => eprintln!("\ncouldn't paste: {e}"),
+        }
+    }
+
+    /// Save the screen to the next unused `screenshot-N.png`, in the current
+    /// directory.
+    fn screenshot(&self) {
+        let path = (1..)
+            .map(|n| PathBuf::from(format!("screenshot-{n}.png")))
+            .find(|path| !path.exists())
+            .unwrap();
+
+        // (Without holding the lock while encoding, so the CPU can keep going.)
+        let frame = self.emu.lock().unwrap().draw_screen();
+        match screenshot::save_png(&frame, &path, 1) {
+            Ok(()) => eprintln!("\nsaved {}", path.display()),
+            Err(e) => eprintln!("\nfailed to save screenshot: {e}"),
         }
     }
 
     fn redraw(&mut self) -> StdResult<(), SoftBufferError> {
-        let dots = self.emu.lock().unwrap().draw_screen();
+        let (frame, number, lines) = self.emu.lock().unwrap().draw_screen_since(self.painted);
 
         let surface = self.surface.as_mut().unwrap();
         surface.resize(
@@ -193,32 +367,58 @@ impl Gui {
         )?;
 
         let mut buf = surface.buffer_mut()?;
-        paint_surface(&dots, &mut buf);
+        // We can leave the other lines alone, as long as the buffer still
+        // has the last frame we painted in it. (But not with the effects,
+        // which change every pixel.)
+        let partial = buf.age() == 1 && !self.effects.persistence && !self.effects.scanlines;
+        let lines = if partial { lines } else { 0..display::H };
+        paint_surface(&frame, &mut buf, lines.clone());
+
+        if self.effects.persistence {
+            effects::persistence(&mut buf, &mut self.afterglow);
+        }
+        if self.effects.scanlines {
+            effects::scanlines(&mut buf, hgr::W * SCALE, SCALE);
+        }
 
         self.window.as_ref().unwrap().pre_present_notify();
-        buf.present()?;
+        // (Nothing, if no lines changed.)
+        let damage = NonZeroU32::new((lines.len() * SCALE) as u32).map(|height| Rect {
+            x: 0,
+            y: (lines.start * SCALE) as u32,
+            width: NonZeroU32::new(DESIRED_WINDOW_SIZE.width).unwrap(),
+            height,
+        });
+        buf.present_with_damage(damage.as_slice())?;
+        self.painted = number;
 
         Ok(())
     }
 }
 
-fn paint_surface(dots: &Vec<Vec<Color>>, buf: &mut [u32]) {
-    for y in 0..hgr::H {
-        for x in 0..hgr::W {
-            let rgb = dots[y][x].rgb();
-            let pixel = pack_rgb(rgb);
-            for i in 0..SCALE {
-                let row = y * SCALE + i;
-                let col = x;
-                buf[(row * hgr::W + col) * SCALE..][..SCALE].fill(pixel);
-            }
+/// Scale these lines of the frame up into the window.
+fn paint_surface(frame: &[u32], buf: &mut [u32], lines: Range<usize>) {
+    let width = hgr::W * SCALE;
+
+    for y in lines {
+        // Each row of the frame is twice as wide as the hi-res screen, so
+        // each pair of dots gets `SCALE` pixels: both dots, with a blend of
+        // the two in between. (Rather than giving every other dot an extra
+        // pixel, which makes the columns uneven.)
+        let src = &frame[y * display::W..][..display::W];
+        let dst = &mut buf[y * SCALE * width..][..SCALE * width];
+        for (dots, pixels) in src.chunks_exact(2).zip(dst.chunks_exact_mut(SCALE)) {
+            pixels.copy_from_slice(&[dots[0], blend(dots[0], dots[1]), dots[1]]);
+        }
+        for i in 1..SCALE {
+            dst.copy_within(..width, i * width);
         }
     }
 }
 
-fn pack_rgb([r, g, b]: [u8; 3]) -> u32 {
-    let r = r as u32;
-    let g = g as u32;
-    let b = b as u32;
-    r << 16 | g << 8 | b
+/// The average of two colors, channel by channel.
+fn blend(a: u32, b: u32) -> u32 {
+    let a = a.to_be_bytes();
+    let b = b.to_be_bytes();
+    u32::from_be_bytes([0, 1, 2, 3].map(|i| ((a[i] as u16 + b[i] as u16) / 2) as u8))
 }
@@ -0,0 +1,58 @@
+//! Post-processing, applied to the window's pixels after the emulated screen
+//! has been drawn. These try to mimic what a real CRT looks like.
+
+/// Which effects are turned on.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Effects {
+    /// Darken the gaps between scanlines.
+    pub scanlines: bool,
+    /// Phosphors keep glowing for a little while after the beam passes, so
+    /// moving objects leave a faint trail.
+    pub persistence: bool,
+}
+
+/// How much of the previous frame's brightness is left over, out of 256.
+const AFTERGLOW: u32 = 128;
+
+/// Darken every `scale`th row of pixels.
+pub fn scanlines(buf: &mut [u32], width: usize, scale: usize) {
+    for (y, row) in buf.chunks_exact_mut(width).enumerate() {
+        if y % scale == scale - 1 {
+            for pixel in row {
+                *pixel = dim(*pixel, 96);
+            }
+        }
+    }
+}
+
+/// Blend in a faded copy of the previous frame, then remember the result for
+/// next time.
+pub fn persistence(buf: &mut [u32], prev: &mut Vec<u32>) {
+    if prev.len() != buf.len() {
+        *prev = buf.to_vec();
+        return;
+    }
+
+    for (pixel, old) in buf.iter_mut().zip(prev.iter_mut()) {
+        *pixel = brightest(*pixel, dim(*old, AFTERGLOW));
+        *old = *pixel;
+    }
+}
+
+/// Scale each color channel by `amount / 256`.
+fn dim(pixel: u32, amount: u32) -> u32 {
+    let [_, r, g, b] = pixel.to_be_bytes();
+    let [r, g, b] = [r, g, b].map(|x| (x as u32 * amount / 256) as u8);
+    u32::from_be_bytes([0, r, g, b])
+}
+
+/// Channel-wise max.
+fn brightest(a: u32, b: u32) -> u32 {
+    let a = a.to_be_bytes();
+    let b = b.to_be_bytes();
+    let mut out = [0; 4];
+    for i in 0..4 {
+        out[i] = a[i].max(b[i]);
+    }
+    u32::from_be_bytes(out)
+}
@@ -0,0 +1,87 @@
+//! The last few instructions the CPU ran, for `history`. So when we end up
+//! somewhere weird (like executing data), we can see how we got there.
+//!
+//! This runs for every instruction, so it's just a fixed-size ring buffer of
+//! the registers. The disassembly happens later, from whatever's in memory
+//! by then. (So self-modifying code might look a bit off.)
+
+use crate::cpu::{Cpu, Register};
+
+/// How many instructions we remember.
+pub const LEN: usize = 1024;
+
+/// The registers just before an instruction ran.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Entry {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub p: u8,
+}
+
+pub struct History {
+    entries: Box<[Entry; LEN]>,
+    /// Where the next one goes.
+    next: usize,
+    len: usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            entries: Box::new([Entry::default(); LEN]),
+            next: 0,
+            len: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+    }
+
+    /// Call this just before the CPU runs the instruction at its PC.
+    pub fn record(&mut self, cpu: &Cpu) {
+        let reg = |r| cpu.register(r) as u8;
+        self.entries[self.next] = Entry {
+            pc: cpu.pc(),
+            a: reg(Register::A),
+            x: reg(Register::X),
+            y: reg(Register::Y),
+            sp: reg(Register::Sp),
+            p: reg(Register::P),
+        };
+        self.next = (self.next + 1) % LEN;
+        self.len = (self.len + 1).min(LEN);
+    }
+
+    /// The last `n` (or fewer) instructions, oldest first.
+    pub fn last(&self, n: usize) -> impl Iterator<Item = &Entry> {
+        let n = n.min(self.len);
+        let start = (self.next + LEN - n) % LEN;
+        (0..n).map(move |i| &self.entries[(start + i) % LEN])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_around() {
+        let mut cpu = Cpu::new(0);
+        let mut history = History::new();
+        assert_eq!(history.last(5).count(), 0);
+
+        for pc in 0..LEN as u16 + 10 {
+            cpu.set_register(Register::Pc, pc).unwrap();
+            history.record(&cpu);
+        }
+        let pcs = history.last(3).map(|entry| entry.pc).collect::<Vec<_>>();
+        assert_eq!(pcs, [LEN as u16 + 7, LEN as u16 + 8, LEN as u16 + 9]);
+        assert_eq!(history.last(usize::MAX).count(), LEN);
+        assert_eq!(history.last(usize::MAX).next().unwrap().pc, 10);
+    }
+}
@@ -0,0 +1,353 @@
+//! Recording all the input (keys, etc.), stamped with the cycle it happened
+//! on, so that a session can be replayed exactly. E.g. to reproduce a bug.
+//!
+//! The file is a save state of the moment recording started, plus two extra
+//! sections: the inputs, and a hash of RAM from when recording stopped.
+//! Replaying checks that we end up with the same hash.
+
+use std::{
+    fs,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    rewind::Input,
+    save_state::{Reader, Sections},
+    Emulator,
+};
+
+pub struct InputRecorder {
+    path: PathBuf,
+    start: Sections,
+    inputs: Vec<(u64, Input)>,
+}
+
+impl InputRecorder {
+    /// `start` is a snapshot of the machine, right now.
+    pub fn new(path: impl AsRef<Path>, start: &[u8]) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        // Write something right away, so a bad path fails now instead of at
+        // the end.
+        fs::write(&path, start).with_context(|| format!("couldn't write {}", path.display()))?;
+
+        Ok(Self {
+            path,
+            start: Sections::from_bytes(start)?,
+            inputs: vec![],
+        })
+    }
+
+    pub fn log(&mut self, cycles: u64, input: &Input) {
+        self.inputs.push((cycles, input.clone()));
+    }
+
+    /// After rewinding, the inputs after `cycles` never happened.
+    pub fn truncate(&mut self, cycles: u64) {
+        self.inputs.retain(|&(c, _)| c <= cycles);
+    }
+
+    /// Returns the number of inputs recorded.
+    pub fn finish(mut self, cycles: u64, ram_hash: u64) -> Result<usize> {
+        let mut data = vec![];
+        for (cycles, input) in &self.inputs {
+            data.extend(cycles.to_le_bytes());
+            encode(input, &mut data);
+        }
+        self.start.add(b"INPT", data);
+
+        let mut end = cycles.to_le_bytes().to_vec();
+        end.extend(ram_hash.to_le_bytes());
+        self.start.add(b"END ", end);
+
+        fs::write(&self.path, self.start.to_bytes())?;
+        Ok(self.inputs.len())
+    }
+}
+
+pub struct InputPlayer {
+    inputs: Vec<(u64, Input)>,
+    next: usize,
+    /// When recording stopped, and what RAM looked like then.
+    end_cycles: u64,
+    ram_hash: u64,
+}
+
+impl InputPlayer {
+    pub fn new(sections: &Sections) -> Result<Self> {
+        let mut r = sections.get(b"INPT")?;
+        let mut inputs = vec![];
+        while !r.is_empty() {
+            let cycles = r.u64()?;
+            inputs.push((cycles, decode(&mut r)?));
+        }
+
+        let mut end = sections.get(b"END ")?;
+        Ok(Self {
+            inputs,
+            next: 0,
+            end_cycles: end.u64()?,
+            ram_hash: end.u64()?,
+        })
+    }
+
+    /// The next input, if it's time for it.
+    pub fn next_input(&mut self, cycles: u64) -> Option<Input> {
+        let (c, input) = self.inputs.get(self.next)?;
+        if *c > cycles {
+            return None;
+        }
+        self.next += 1;
+        Some(input.clone())
+    }
+
+    pub fn is_finished(&self, cycles: u64) -> bool {
+        self.next == self.inputs.len() && cycles >= self.end_cycles
+    }
+
+    /// After rewinding, pick up from the first input after `cycles`. (Any
+    /// inputs at `cycles` itself have already happened.)
+    pub fn seek(&mut self, cycles: u64) {
+        self.next = self.inputs.partition_point(|&(c, _)| c <= cycles);
+    }
+}
+
+fn encode(input: &Input, out: &mut Vec<u8>) {
+    match input {
+        Input::KeyDown(ascii_code) => out.extend([0, *ascii_code]),
+        Input::KeyUp(ascii_code) => out.extend([1, *ascii_code]),
+        Input::AllKeysUp => out.push(2),
+        Input::AppleKeys {
+            open_apple,
+            solid_apple,
+        } => out.extend([3, *open_apple as u8, *solid_apple as u8]),
+        Input::Type(text) => {
+            out.push(4);
+            out.extend((text.len() as u32).to_le_bytes());
+            out.extend(text.as_bytes());
+        }
+        Input::Reset => out.push(5),
+    }
+}
+
+fn decode(r: &mut Reader) -> Result<Input> {
+    Ok(match r.u8()? {
+        0 => Input::KeyDown(r.u8()? & 0x7f),
+        1 => Input::KeyUp(r.u8()? & 0x7f),
+        2 => Input::AllKeysUp,
+        3 => Input::AppleKeys {
+            open_apple: r.bool()?,
+            solid_apple: r.bool()?,
+        },
+        4 => {
+            let len = r.u32()? as usize;
+            let text = String::from_utf8(r.bytes(len)?.to_vec()).context("invalid text")?;
+            Input::Type(text)
+        }
+        5 => Input::Reset,
+        kind => bail!("unknown kind of input: {kind}"),
+    })
+}
+
+impl Emulator {
+    /// Start logging every input to a file. See `InputRecorder`.
+    pub fn start_input_recording(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.stop_input_recording()?;
+        self.input_recorder = Some(InputRecorder::new(path, &self.snapshot())?);
+        Ok(())
+    }
+
+    /// Returns the number of inputs recorded, or `None` if we weren't
+    /// recording.
+    pub fn stop_input_recording(&mut self) -> Result<Option<usize>> {
+        match self.input_recorder.take() {
+            Some(recorder) => Ok(Some(recorder.finish(self.cycles, self.ram_hash())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Go back to where the recording started, and play the same inputs
+    /// again. Until the replay finishes, any other input is ignored.
+    pub fn replay_input(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).with_context(|| format!("couldn't read {}", path.display()))?;
+        let sections = Sections::from_bytes(&bytes)?;
+        let player = InputPlayer::new(&sections)
+            .with_context(|| format!("{} isn't an input recording", path.display()))?;
+
+        // (Not `restore`, which lets go of all the keys. That'd be a
+        // different start than the recording had.)
+        let end = self.input_recording_end();
+        let breakpoints = self.breakpoints.clone();
+        self.restore_machine(&bytes)?;
+        self.breakpoints = breakpoints;
+        self.rewind.clear();
+        self.finish_input_recording(end);
+        self.input_player = Some(player);
+        Ok(())
+    }
+
+    /// Before jumping to another state: where the input recording (if any)
+    /// should end. See `finish_input_recording`.
+    pub(crate) fn input_recording_end(&self) -> Option<(u64, u64)> {
+        self.input_recorder
+            .is_some()
+            .then(|| (self.cycles, self.ram_hash()))
+    }
+
+    /// After jumping to another state, the inputs from here on don't follow
+    /// from the recording's start. So it stops just before the jump. (That
+    /// way it still replays.) The jump has already happened by now, so if
+    /// saving fails, just say so.
+    pub(crate) fn finish_input_recording(&mut self, end: Option<(u64, u64)>) {
+        let (Some(recorder), Some((cycles, ram_hash))) = (self.input_recorder.take(), end) else {
+            return;
+        };
+        match recorder.finish(cycles, ram_hash) {
+            Ok(n) => {
+                eprintln!("stopped recording input: recorded {n} inputs (RAM hash {ram_hash:016x})")
+            }
+            Err(e) => eprintln!("stopped recording input, but couldn't save it: {e}"),
+        }
+    }
+
+    pub fn ram_hash(&self) -> u64 {
+        self.mem.ram_hash()
+    }
+
+    /// Feed in any recorded inputs that are due. Called before every
+    /// instruction. Breaks when the replay finishes.
+    pub(crate) fn play_inputs(&mut self) -> ControlFlow<()> {
+        loop {
+            let Some(player) = &mut self.input_player else {
+                return ControlFlow::Continue(());
+            };
+
+            if let Some(input) = player.next_input(self.cycles) {
+                self.feed_input(input);
+                continue;
+            }
+
+            if player.is_finished(self.cycles) {
+                let expected = player.ram_hash;
+                self.input_player = None;
+                // Stop here, so you can poke around.
+                self.halted = true;
+
+                let hash = self.ram_hash();
+                if hash == expected {
+                    eprintln!("\nreplay finished; RAM matches ({hash:016x})");
+                } else {
+                    eprintln!(
+                        "\nreplay diverged! RAM hash is {hash:016x}, expected {expected:016x}"
+                    );
+                }
+                return ControlFlow::Break(());
+            }
+            return ControlFlow::Continue(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inputs_round_trip() {
+        let inputs = [
+            Input::KeyDown(b'A'),
+            Input::KeyUp(b'A'),
+            Input::AllKeysUp,
+            Input::AppleKeys {
+                open_apple: true,
+                solid_apple: false,
+            },
+            Input::Type("RUN\r".to_string()),
+            Input::Reset,
+        ];
+
+        let mut data = vec![];
+        for input in &inputs {
+            encode(input, &mut data);
+        }
+        let mut sections = Sections::new();
+        sections.add(b"TEST", data);
+
+        let mut r = sections.get(b"TEST").unwrap();
+        for input in &inputs {
+            assert_eq!(&decode(&mut r).unwrap(), input);
+        }
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn loading_a_state_stops_recording() {
+        // JMP $0300
+        let mut emu = Emulator::new(&[0x4c, 0x00, 0x03], 0x300, 0x300, vec![]);
+        let state = emu.snapshot();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("inputs");
+        emu.start_input_recording(&path).unwrap();
+        emu.sim_1000_instrs();
+        emu.key_down(b'A');
+        emu.sim_1000_instrs();
+        let (cycles, ram_hash) = (emu.cycles, emu.ram_hash());
+
+        emu.restore(&state).unwrap();
+        assert_eq!(emu.stop_input_recording().unwrap(), None);
+
+        // The recording ends where we were when we loaded the state.
+        let bytes = fs::read(&path).unwrap();
+        let player = InputPlayer::new(&Sections::from_bytes(&bytes).unwrap()).unwrap();
+        assert_eq!(player.inputs.len(), 1);
+        assert_eq!((player.end_cycles, player.ram_hash), (cycles, ram_hash));
+    }
+
+    #[test]
+    fn replaying_stops_recording() {
+        let mut emu = Emulator::new(&[0x4c, 0x00, 0x03], 0x300, 0x300, vec![]);
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = (dir.path().join("first"), dir.path().join("second"));
+        emu.start_input_recording(&first).unwrap();
+        emu.sim_1000_instrs();
+        emu.key_down(b'A');
+        emu.sim_1000_instrs();
+        emu.stop_input_recording().unwrap();
+
+        emu.start_input_recording(&second).unwrap();
+        emu.sim_1000_instrs();
+        emu.key_down(b'B');
+        emu.sim_1000_instrs();
+        let (cycles, ram_hash) = (emu.cycles, emu.ram_hash());
+
+        emu.replay_input(&first).unwrap();
+        assert_eq!(emu.stop_input_recording().unwrap(), None);
+
+        // The second recording ends where we were when the replay started,
+        // without any of the replayed inputs.
+        let bytes = fs::read(&second).unwrap();
+        let player = InputPlayer::new(&Sections::from_bytes(&bytes).unwrap()).unwrap();
+        assert_eq!(player.inputs.len(), 1);
+        assert_eq!((player.end_cycles, player.ram_hash), (cycles, ram_hash));
+    }
+
+    #[test]
+    fn failing_to_save_the_recording_still_loads_the_state() {
+        let mut emu = Emulator::new(&[0x4c, 0x00, 0x03], 0x300, 0x300, vec![]);
+        let state = emu.snapshot();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("inputs");
+        emu.start_input_recording(&path).unwrap();
+        emu.sim_1000_instrs();
+        // Now it can't be written.
+        fs::remove_file(&path).unwrap();
+        fs::create_dir(&path).unwrap();
+
+        emu.restore(&state).unwrap();
+        assert!(emu.snapshot() == state);
+        assert_eq!(emu.stop_input_recording().unwrap(), None);
+    }
+}
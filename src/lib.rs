@@ -1,42 +1,84 @@
 #![allow(unused_imports)] // todo
 
-use std::ops::ControlFlow;
+use std::{
+    fs,
+    ops::{ControlFlow, Range, RangeInclusive},
+    path::Path,
+    sync::Arc,
+};
 
 use anyhow::Result;
-use cpu::{instr::Instr, Cpu};
-use debugger_commands::Command;
-use display::{color::Color, gr, hgr, text};
+use cpu::{instr::Instr, Cpu, Register};
+use debugger_commands::{Breakpoint, Command, StopAt};
+use display::text;
+use history::History;
+use input_log::{InputPlayer, InputRecorder};
 use itertools::Itertools;
 use memory::AddressSpace;
+use recording::Recorder;
+use rewind::{Input, Rewind};
+use save_state::Sections;
+use symbols::SymbolTable;
+use trace::Tracer;
 
 mod cpu;
 pub mod debugger_commands;
 mod display;
+#[cfg(feature = "gui")]
 pub mod gui;
 pub mod hex;
+mod history;
+mod input_log;
 mod memory;
+pub mod recording;
+mod rewind;
+pub mod save_state;
+pub mod screenshot;
+mod speaker;
+pub mod symbols;
+mod trace;
+#[cfg(feature = "tui")]
+pub mod tui;
+mod video;
+
+pub use display::{text::Attr as TextAttr, Frame, Monitor};
+pub use memory::watchpoints::{WatchKind, Watchpoint};
 
 pub struct Emulator {
     cpu: Cpu,
     mem: AddressSpace,
     halted: bool,
     num_instructions_executed: u64,
-    breakpoints: Vec<u16>,
+    /// Total number of clock cycles, since the emulator started.
+    cycles: u64,
+    breakpoints: Vec<Breakpoint>,
+    /// For the disassembler, and the debugger.
+    symbols: SymbolTable,
     /// If a `finish` command is ongoing, this stores the current subroutine
     /// depth, e.g.:
     /// * 0 if we haven't called any inner subroutines
     /// * 3 if we're 3 subroutines deep
+    ///
     /// And when it would go negative, we know we've returned from the top-level
     /// subroutine.
     ///
     /// NB: the Apple IIe 80col ROM uses hacks and tricks (like RTS without a
     /// JSR), so this will sometimes halt earlier than you expect.
     finish_state: Option<usize>,
+    /// For `next`, `until`, and `call`.
+    stop_at: Option<StopAt>,
+    /// The last few instructions, for `history`.
+    history: History,
+    recorder: Option<Recorder>,
+    tracer: Option<Tracer>,
+    rewind: Rewind,
+    input_recorder: Option<InputRecorder>,
+    input_player: Option<InputPlayer>,
 }
 
 impl Emulator {
     pub fn new(program: &[u8], load_addr: u16, start_addr: u16, breakpoints: Vec<u16>) -> Self {
-        let mut mem = AddressSpace::new(&program, load_addr);
+        let mut mem = AddressSpace::new(program, load_addr);
         let pc = mem.set_softev(start_addr);
 
         Self {
@@ -44,8 +86,17 @@ impl Emulator {
             mem,
             halted: false,
             num_instructions_executed: 0,
-            breakpoints,
+            cycles: 0,
+            breakpoints: breakpoints.into_iter().map(Breakpoint::new).collect(),
             finish_state: None,
+            stop_at: None,
+            history: History::new(),
+            symbols: SymbolTable::builtin(),
+            recorder: None,
+            tracer: None,
+            rewind: Rewind::new(),
+            input_recorder: None,
+            input_player: None,
         }
     }
 
@@ -62,8 +113,17 @@ impl Emulator {
             mem,
             halted: false,
             num_instructions_executed: 0,
-            breakpoints,
+            cycles: 0,
+            breakpoints: breakpoints.into_iter().map(Breakpoint::new).collect(),
             finish_state: None,
+            stop_at: None,
+            history: History::new(),
+            symbols: SymbolTable::builtin(),
+            recorder: None,
+            tracer: None,
+            rewind: Rewind::new(),
+            input_recorder: None,
+            input_player: None,
         })
     }
 
@@ -81,15 +141,73 @@ impl Emulator {
 
         if self.check_breakpoints().is_break() {
             self.halted = true;
+            // E.g. if we hit a breakpoint during `next`, forget about it.
+            self.stop_at = None;
 
-            eprintln!("{}", self.cpu.dbg_next_instr(&mut self.mem));
+            eprintln!("{}", self.cpu.dbg_next_instr(&mut self.mem, &self.symbols));
             eprint!("... ");
 
             return;
         }
 
-        self.cpu.step(&mut self.mem);
+        self.execute_instr();
+
+        // E.g. a watchpoint.
+        if self.halted {
+            self.stop_at = None;
+            eprintln!("{}", self.cpu.dbg_next_instr(&mut self.mem, &self.symbols));
+            eprint!("... ");
+        }
+    }
+
+    /// Run the next instruction, ignoring any breakpoints. (But watchpoints
+    /// still halt, after the instruction that triggers them.)
+    fn execute_instr(&mut self) {
+        if self.play_inputs().is_break() {
+            return;
+        }
+
+        // Only count what the CPU does. (Not e.g. the debugger looking
+        // around.)
+        self.mem.take_watch_hits();
+        let pc = self.cpu.pc();
+        self.history.record(&self.cpu);
+        if let Some(tracer) = &mut self.tracer {
+            if let Err(e) = tracer.log(&self.cpu, &self.mem, &self.symbols, self.cycles) {
+                eprintln!("\ntrace failed: {e}");
+                self.tracer = None;
+            }
+        }
+        let cycles = self.cpu.step(&mut self.mem);
+        let hits = self.mem.take_watch_hits();
+        if !hits.is_empty() {
+            eprintln!();
+            for hit in hits {
+                eprintln!("watchpoint: {hit} (by the instruction at ${pc:04x})");
+            }
+            self.halted = true;
+        }
+
+        let end_of_frame = self.mem.tick(cycles);
         self.num_instructions_executed += 1;
+        self.cycles += cycles as u64;
+
+        if end_of_frame {
+            self.end_of_frame();
+        }
+        self.maybe_take_snapshot();
+    }
+
+    fn end_of_frame(&mut self) {
+        let toggles = self.mem.take_speaker_toggles();
+
+        if let Some(recorder) = &mut self.recorder {
+            let frame = self.mem.video_frame().unwrap();
+            if let Err(e) = recorder.frame(frame, &toggles, self.cycles) {
+                eprintln!("\nrecording failed: {e}");
+                self.recorder = None;
+            }
+        }
     }
 
     fn check_breakpoints(&mut self) -> ControlFlow<()> {
@@ -108,9 +226,26 @@ impl Emulator {
             return ControlFlow::Break(());
         }
 
-        if self.breakpoints.contains(&self.cpu.pc()) {
-            eprintln!("\nhit breakpoint");
-            return ControlFlow::Break(());
+        let pc = self.cpu.pc();
+        if let Some(i) = self.breakpoints.iter().position(|bp| bp.addr == pc) {
+            if self.breakpoints[i].condition_holds(self) {
+                let bp = &mut self.breakpoints[i];
+                if bp.ignore_count > 0 {
+                    bp.ignore_count -= 1;
+                } else {
+                    eprintln!("\nhit breakpoint");
+                    return ControlFlow::Break(());
+                }
+            }
+        }
+
+        if let Some(stop_at) = self.stop_at {
+            let sp = self.cpu.register(Register::Sp) as u8;
+            if pc == stop_at.pc && stop_at.sp.is_none_or(|s| s == sp) {
+                eprintln!("\n{} (${pc:04x})", stop_at.message);
+                self.stop_at = None;
+                return ControlFlow::Break(());
+            }
         }
 
         if let Some(depth) = self.finish_state.as_mut() {
@@ -119,8 +254,7 @@ impl Emulator {
                 Instr::Jsr => *depth += 1,
                 Instr::Rts => {
                     if *depth == 0 {
-                        self.cpu.step(&mut self.mem);
-                        self.num_instructions_executed += 1;
+                        self.execute_instr();
 
                         eprintln!("\nfinished subroutine");
                         self.finish_state = None;
@@ -136,17 +270,312 @@ impl Emulator {
         ControlFlow::Continue(())
     }
 
-    /// Called at 60 Hz.
-    pub fn draw_screen(&self) -> Vec<Vec<Color>> {
-        self.mem.display()
+    /// Called at 60 Hz. This is cheap while running, since the frame's
+    /// already drawn, and it's shared rather than copied.
+    pub fn draw_screen(&self) -> Arc<Frame> {
+        self.draw_screen_since(None).0
+    }
+
+    /// Like `draw_screen`, for when frame number `since` is still on screen.
+    /// Also returns the new frame's number, and which lines may differ from
+    /// the old one. (Frames rendered while halted don't get a number, so
+    /// every line may differ from them.)
+    pub fn draw_screen_since(&self, since: Option<u64>) -> (Arc<Frame>, Option<u64>, Range<usize>) {
+        // While we're halted the beam isn't moving, so show what's in memory
+        // right now. (E.g. so that changes made in the debugger show up.)
+        if !self.halted {
+            if let Some(frame) = self.mem.video_frame() {
+                let number = self.mem.video_frame_number();
+                let lines = match since {
+                    Some(since) => self.mem.video_lines_redrawn_since(since),
+                    None => 0..display::H,
+                };
+                return (Arc::clone(frame), Some(number), lines);
+            }
+        }
+        (Arc::new(self.mem.display()), None, 0..display::H)
+    }
+
+    /// The current text page (40 or 80 columns), one string per row.
+    /// Inverse and flashing characters come out as plain characters; see
+    /// `screen_attrs` to tell them apart.
+    ///
+    /// This always decodes all 24 rows, whether or not we're in text mode. In
+    /// mixed mode, the bottom 4 rows are the ones on screen.
+    pub fn screen_text(&self) -> Vec<String> {
+        let page = self.mem.text_page();
+        page.iter()
+            .map(|row| row.iter().map(|&b| text::decode(b).0).collect())
+            .collect()
+    }
+
+    /// How each character of `screen_text` is displayed.
+    pub fn screen_attrs(&self) -> Vec<Vec<TextAttr>> {
+        let page = self.mem.text_page();
+        page.iter()
+            .map(|row| row.iter().map(|&b| text::decode(b).1).collect())
+            .collect()
+    }
+
+    /// Save the current screen to a PNG file. See `screenshot::save_png`.
+    pub fn save_screenshot(&self, path: impl AsRef<Path>, scale: usize) -> Result<()> {
+        screenshot::save_png(&self.draw_screen(), path, scale)
+    }
+
+    /// A snapshot of the whole machine (plus the breakpoints). See
+    /// `save_state` for the format.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut sections = Sections::new();
+
+        let mut counters = self.cycles.to_le_bytes().to_vec();
+        counters.extend(self.num_instructions_executed.to_le_bytes());
+        sections.add(b"EMU ", counters);
+
+        let breakpoints = self.breakpoints.iter().flat_map(|bp| bp.addr.to_le_bytes());
+        sections.add(b"BRKP", breakpoints.collect());
+        // (Separately, so older versions can still load the addresses.)
+        let mut conditions = vec![];
+        for bp in &self.breakpoints {
+            let condition = bp.condition.as_ref().map(|c| c.to_string());
+            let condition = condition.unwrap_or_default();
+            conditions.extend(bp.ignore_count.to_le_bytes());
+            conditions.extend((condition.len() as u32).to_le_bytes());
+            conditions.extend(condition.as_bytes());
+        }
+        sections.add(b"BCND", conditions);
+
+        self.cpu.save_state(&mut sections);
+        self.mem.save_state(&mut sections);
+        sections.to_bytes()
+    }
+
+    /// Go back to a snapshot, from `snapshot`.
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        let end = self.input_recording_end();
+
+        self.restore_machine(snapshot)?;
+        // Whatever keys were held when the snapshot was taken, they probably
+        // aren't now.
+        self.mem.all_keys_up();
+        // There's no going back from here: the history we have doesn't lead
+        // to this state.
+        self.rewind.clear();
+        // Same goes for the input recording.
+        self.finish_input_recording(end);
+        self.input_player = None;
+        Ok(())
+    }
+
+    fn restore_machine(&mut self, snapshot: &[u8]) -> Result<()> {
+        let sections = Sections::from_bytes(snapshot)?;
+
+        let mut counters = sections.get(b"EMU ")?;
+        let cycles = counters.u64()?;
+        let num_instructions_executed = counters.u64()?;
+
+        let mut r = sections.get(b"BRKP")?;
+        let mut breakpoints = vec![];
+        while !r.is_empty() {
+            breakpoints.push(Breakpoint::new(r.u16()?));
+        }
+        if let Some(mut r) = sections.find(b"BCND") {
+            for bp in &mut breakpoints {
+                bp.ignore_count = r.u32()?;
+                let len = r.u32()? as usize;
+                let condition = String::from_utf8_lossy(r.bytes(len)?);
+                if !condition.is_empty() {
+                    bp.condition = Some(condition.parse()?);
+                }
+            }
+        }
+
+        // Load into copies, and only swap them in once everything's loaded,
+        // so a bad file doesn't leave us half-loaded.
+        let mut mem = self.mem.clone();
+        mem.load_state(&sections)?;
+        let mut cpu = self.cpu.clone();
+        cpu.load_state(&sections)?;
+
+        self.mem = mem;
+        self.cpu = cpu;
+        self.cycles = cycles;
+        self.num_instructions_executed = num_instructions_executed;
+        self.breakpoints = breakpoints;
+        self.finish_state = None;
+        self.stop_at = None;
+        // (Rewinding replays from here, so it fills back in.)
+        self.history.clear();
+        Ok(())
+    }
+
+    pub fn save_state(&self, path: impl AsRef<Path>) -> Result<()> {
+        fs::write(path, self.snapshot())?;
+        Ok(())
+    }
+
+    pub fn load_state(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.restore(&fs::read(path)?)
+    }
+
+    /// Start recording video and audio. See `Recorder::new`.
+    pub fn start_recording(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(Recorder::new(path, self.cycles)?);
+        Ok(())
+    }
+
+    /// Returns the number of frames recorded, or `None` if we weren't
+    /// recording.
+    pub fn stop_recording(&mut self) -> Result<Option<u64>> {
+        match self.recorder.take() {
+            Some(recorder) => Ok(Some(recorder.finish()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Log every instruction in `range` to a file. See `Tracer`. With
+    /// `symbols`, each line ends with a comment saying where it is. Returns
+    /// where it's going.
+    pub fn start_trace(
+        &mut self,
+        path: impl AsRef<Path>,
+        range: RangeInclusive<u16>,
+        symbols: bool,
+    ) -> Result<&Path> {
+        self.stop_trace()?;
+        let tracer = self.tracer.insert(Tracer::new(path, range)?);
+        tracer.symbols = symbols;
+        Ok(tracer.path())
+    }
+
+    /// Carry on after `pause_trace`, optionally with a different range. (Or
+    /// start tracing to `trace.log`, if we weren't.)
+    pub fn resume_trace(
+        &mut self,
+        range: Option<RangeInclusive<u16>>,
+        symbols: bool,
+    ) -> Result<&Path> {
+        match &mut self.tracer {
+            Some(tracer) => {
+                tracer.on = true;
+                tracer.symbols = symbols;
+                if let Some(range) = range {
+                    tracer.range = range;
+                }
+            }
+            None => {
+                self.start_trace("trace.log", range.unwrap_or(0..=0xffff), symbols)?;
+            }
+        }
+        Ok(self.tracer.as_ref().unwrap().path())
+    }
+
+    /// Stop logging instructions, but keep the file open. Returns `false` if
+    /// we weren't tracing.
+    pub fn pause_trace(&mut self) -> Result<bool> {
+        match &mut self.tracer {
+            Some(tracer) if tracer.on => {
+                tracer.on = false;
+                tracer.flush()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Returns the number of instructions logged, or `None` if we weren't
+    /// tracing.
+    pub fn stop_trace(&mut self) -> Result<Option<u64>> {
+        match self.tracer.take() {
+            Some(tracer) => Ok(Some(tracer.finish()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// See `SymbolTable::load`.
+    pub fn load_symbols(&mut self, path: impl AsRef<Path>) -> Result<usize> {
+        self.symbols.load(path)
+    }
+
+    /// See `SymbolTable::resolve`.
+    pub fn resolve_symbol(&self, s: &str) -> Result<u16> {
+        self.symbols.resolve(s)
+    }
+
+    pub fn monitor(&self) -> Monitor {
+        self.mem.monitor()
+    }
+
+    pub fn set_monitor(&mut self, monitor: Monitor) {
+        self.mem.set_monitor(monitor);
     }
 
     pub fn key_down(&mut self, ascii_code: u8) {
-        self.mem.key_down(ascii_code);
+        self.input(Input::KeyDown(ascii_code));
+    }
+
+    pub fn key_up(&mut self, ascii_code: u8) {
+        self.input(Input::KeyUp(ascii_code));
     }
 
     pub fn all_keys_up(&mut self) {
-        self.mem.all_keys_up();
+        self.input(Input::AllKeysUp);
+    }
+
+    /// The Open Apple and Solid Apple keys. (They aren't like the other keys;
+    /// they're wired up as the joystick buttons.)
+    pub fn set_apple_keys(&mut self, open_apple: bool, solid_apple: bool) {
+        self.input(Input::AppleKeys {
+            open_apple,
+            solid_apple,
+        });
+    }
+
+    /// Ctrl-Reset.
+    pub fn reset(&mut self) {
+        self.input(Input::Reset);
+    }
+
+    /// Type some text, e.g. pasted from the clipboard. The keys get fed to
+    /// the program one at a time, as it reads them.
+    pub fn type_text(&mut self, text: &str) {
+        self.input(Input::Type(text.to_owned()));
+    }
+
+    /// Everything from the outside world comes through here, so it can be
+    /// logged (for rewinding, and recording).
+    fn input(&mut self, input: Input) {
+        // While replaying, the only input comes from the recording.
+        if self.input_player.is_some() {
+            return;
+        }
+        self.feed_input(input);
+    }
+
+    fn feed_input(&mut self, input: Input) {
+        self.apply_input(&input);
+        if let Some(recorder) = &mut self.input_recorder {
+            recorder.log(self.cycles, &input);
+        }
+        self.rewind.log(self.cycles, input);
+    }
+
+    fn apply_input(&mut self, input: &Input) {
+        match input {
+            Input::KeyDown(ascii_code) => self.mem.key_down(*ascii_code),
+            Input::KeyUp(ascii_code) => self.mem.key_up(*ascii_code),
+            Input::AllKeysUp => self.mem.all_keys_up(),
+            Input::AppleKeys {
+                open_apple,
+                solid_apple,
+            } => self.mem.set_apple_keys(*open_apple, *solid_apple),
+            Input::Type(text) => self.mem.type_text(text),
+            Input::Reset => {
+                self.mem.reset();
+                self.cpu.reset(&mut self.mem);
+                self.finish_state = None;
+            }
+        }
     }
 
     /// Execute a "debugger" command.
@@ -154,3 +583,76 @@ impl Emulator {
         cmd.execute(self);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    /// JMP $0300
+    const LOOP: &[u8] = &[0x4c, 0x00, 0x03];
+
+    /// A save state, minus one section.
+    fn without_section(state: &[u8], tag: &[u8; 4]) -> Vec<u8> {
+        // The magic number and version.
+        let mut out = state[..12].to_vec();
+        let mut rest = &state[12..];
+        while !rest.is_empty() {
+            let len = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            let (section, tail) = rest.split_at(8 + len);
+            if &section[..4] != tag {
+                out.extend(section);
+            }
+            rest = tail;
+        }
+        out
+    }
+
+    #[test_case(|state| state[..state.len() - 1].to_vec(); "truncated")]
+    #[test_case(|state| without_section(state, b"CPU "); "no cpu")]
+    #[test_case(|state| without_section(state, b"SPKR"); "no speaker")]
+    fn bad_state_changes_nothing(corrupt: fn(&[u8]) -> Vec<u8>) {
+        let mut emu = Emulator::new(LOOP, 0x300, 0x300, vec![]);
+        emu.sim_1000_instrs();
+        let old = emu.snapshot();
+        emu.sim_1000_instrs();
+        emu.mem.write(0x2000, 0x42);
+
+        let before = emu.snapshot();
+        assert!(emu.restore(&corrupt(&old)).is_err());
+        assert!(emu.snapshot() == before);
+    }
+
+    #[test]
+    fn running_watched_code() {
+        // LDA $0310
+        // JMP $0300
+        let program = [0xad, 0x10, 0x03, 0x4c, 0x00, 0x03];
+        let mut emu = Emulator::new(&program, 0x300, 0x300, vec![]);
+        // Get through the ROM's reset routine.
+        for _ in 0..100 {
+            emu.sim_1000_instrs();
+        }
+        assert!((0x300..0x306).contains(&emu.cpu.pc()));
+
+        // Fetching the instructions doesn't count as reading them.
+        emu.mem.toggle_watchpoint(Watchpoint {
+            start: 0x300,
+            end_inclusive: 0x305,
+            kind: WatchKind::Read,
+        });
+        emu.sim_1000_instrs();
+        assert!(!emu.halted);
+
+        // But the data they read does.
+        emu.mem.toggle_watchpoint(Watchpoint {
+            start: 0x310,
+            end_inclusive: 0x310,
+            kind: WatchKind::Read,
+        });
+        emu.sim_1000_instrs();
+        assert!(emu.halted);
+        assert_eq!(emu.cpu.pc(), 0x303);
+    }
+}
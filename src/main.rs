@@ -4,6 +4,7 @@ use std::{
     env,
     fs::File,
     io::{self, prelude::*},
+    path::Path,
     sync::{
         atomic::{AtomicBool, Ordering::Relaxed},
         Arc, Mutex,
@@ -13,12 +14,18 @@ use std::{
 };
 
 use anyhow::{bail, Context as _, Result};
-use apple_ii_emulator::{debugger_commands::Command, gui::Gui, hex, Emulator};
+#[cfg(feature = "gui")]
+use apple_ii_emulator::gui::{Effects, Gui};
+use apple_ii_emulator::{
+    debugger_commands::{self, Command, Location},
+    hex, Emulator, Monitor,
+};
 use clap::{
     builder::{styling::AnsiColor, Styles},
     command, Parser,
 };
 use itertools::Itertools;
+#[cfg(feature = "gui")]
 use winit::event_loop::{EventLoop, EventLoopClosed};
 
 const HELP_MESSAGE_STYLE: Styles = Styles::styled()
@@ -46,23 +53,106 @@ struct Args {
     #[arg(long, value_name = "START_ADDR")]
     raw_bytes: Option<String>,
 
-    /// Memory address (hexadecimal) to set a breakpoint in the debugger. Can be
-    /// passed multiple times.
+    /// Memory address (hexadecimal), or symbol, to set a breakpoint in the
+    /// debugger. Can be passed multiple times.
     #[arg(long)]
     breakpoint: Vec<String>,
+
+    /// Load symbols for the debugger and disassembler: an ELF file from
+    /// llvm-mos, a ca65/ld65 debug file, or VICE-style labels. Can be passed
+    /// multiple times. (If there's a FILE.elf next to the memory image, that
+    /// gets loaded anyway.)
+    #[arg(long, value_name = "FILE")]
+    symbols: Vec<String>,
+
+    /// What kind of monitor to emulate: composite, rgb, white, green, or
+    /// amber. (Press F2 to switch while running.)
+    #[arg(long, default_value = "composite")]
+    monitor: Monitor,
+
+    /// Darken the gaps between scanlines, like on a CRT. (F3 to toggle.)
+    #[arg(long)]
+    scanlines: bool,
+
+    /// Simulate phosphor persistence, so moving objects leave a faint trail.
+    /// (F4 to toggle.)
+    #[arg(long)]
+    persistence: bool,
+
+    /// Record the screen at 60 Hz, to a Y4M file (if the name ends in .y4m)
+    /// or to a directory of PNGs. The speaker audio goes in a WAV file with
+    /// the same name.
+    #[arg(long, value_name = "PATH")]
+    record: Option<String>,
+
+    /// Record every input (keys, etc.) to this file, stamped with the CPU
+    /// cycle it happened on. Replay it with --replay-input.
+    #[arg(long, value_name = "FILE")]
+    record_input: Option<String>,
+
+    /// Replay a file from --record-input, starting from the same state and
+    /// feeding in the same inputs at exactly the same cycles. When it's done,
+    /// the emulator halts and checks that RAM matches the recording.
+    #[arg(long, value_name = "FILE")]
+    replay_input: Option<String>,
+
+    /// Log every instruction (PC, bytes, disassembly, registers, and cycle
+    /// count) to this file, in the same format as Nintendulator, so it can be
+    /// diffed against other emulators. (Or use the `trace` debugger command.)
+    #[arg(long, value_name = "FILE")]
+    trace: Option<String>,
+
+    /// With --trace: only log instructions in this range, e.g. 800.bfff to
+    /// leave out the ROM.
+    #[arg(long, value_name = "START.END", default_value = "0.ffff")]
+    trace_range: String,
+
+    /// With --trace: end each line with a comment giving the symbol and
+    /// source line, if there are any.
+    #[arg(long)]
+    trace_symbols: bool,
+
+    /// Type this text once the emulator starts, e.g. "RUN\r". (Also: press F5
+    /// to paste from the clipboard, or use the `type` debugger command.)
+    #[arg(long, value_name = "TEXT")]
+    autotype: Option<String>,
+
+    /// Run without a window, just the debugger. The emulator exits when
+    /// stdin closes (or on `quit`).
+    #[arg(long)]
+    headless: bool,
+
+    /// Draw the screen in the terminal, instead of a window. Press F10 to
+    /// quit. (There's no debugger in this mode, since the keyboard goes to
+    /// the emulator. You'll probably want to redirect stderr.)
+    #[arg(long)]
+    tui: bool,
+
+    /// With --headless: keep running for this many seconds, even after stdin
+    /// closes.
+    #[arg(long, value_name = "SECONDS")]
+    run_for: Option<f64>,
+
+    /// Print the text screen to stdout, when exiting.
+    #[arg(long)]
+    dump_text: bool,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
+    // (Symbols have to wait until they're loaded.)
     let mut breakpoints = Vec::with_capacity(args.breakpoint.len());
-    for bp in args.breakpoint {
-        let addr = hex::decode_u16(&bp)?;
-        breakpoints.push(addr);
+    let mut symbol_breakpoints = vec![];
+    for bp in &args.breakpoint {
+        match bp.parse()? {
+            Location::Addr(addr) => breakpoints.push(addr),
+            Location::Symbol(name) => symbol_breakpoints.push(name),
+        }
     }
 
-    let emu = if let Some(load_addr) = args.raw_bytes {
-        let load_addr = hex::decode_u16(&load_addr)?;
+    let mut emu = if let Some(load_addr) = &args.raw_bytes {
+        let load_addr = hex::decode_u16(load_addr)?;
         let start_addr = load_addr;
 
         let mut file = File::open(&args.memory_image_file)?;
@@ -79,15 +169,103 @@ fn main() -> Result<()> {
         // Read the file headers.
         Emulator::from_memory_image(&bytes, breakpoints)?
     };
+    let elf_path = format!("{}.elf", args.memory_image_file);
+    let mut symbol_paths = args.symbols.clone();
+    if args.raw_bytes.is_none() && Path::new(&elf_path).exists() {
+        symbol_paths.insert(0, elf_path);
+    }
+    for path in &symbol_paths {
+        let n = emu.load_symbols(path)?;
+        eprintln!("loaded {n} symbols from {path}");
+    }
+    for name in symbol_breakpoints {
+        let addr = emu.resolve_symbol(&name).context("invalid --breakpoint")?;
+        emu.control(Command::SetBreakpoint {
+            addr: Location::Addr(addr),
+            condition: None,
+            ignore_count: 0,
+        });
+    }
+
+    emu.set_monitor(args.monitor);
+    if let Some(text) = &args.autotype {
+        emu.type_text(&debugger_commands::unescape(text));
+    }
+    if let Some(path) = &args.replay_input {
+        emu.replay_input(path)?;
+    }
+    if let Some(path) = &args.record_input {
+        emu.start_input_recording(path)?;
+    }
+    if let Some(path) = &args.record {
+        emu.start_recording(path)?;
+    }
+    if let Some(path) = &args.trace {
+        let range = debugger_commands::parse_range(&args.trace_range)?;
+        emu.start_trace(path, range, args.trace_symbols)?;
+    }
     let emu = Arc::new(Mutex::new(emu));
 
     let emu1 = Arc::clone(&emu);
     thread::spawn(move || run_cpu(emu1));
 
+    if args.headless {
+        let emu1 = Arc::clone(&emu);
+        let debugger = thread::spawn(move || run_debugger(emu1));
+
+        match args.run_for {
+            Some(seconds) => thread::sleep(Duration::from_secs_f64(seconds)),
+            None => match debugger.join().unwrap() {
+                Ok(()) => (),
+                Err(e) => eprintln!("\n{e}"),
+            },
+        }
+
+        return shut_down(&emu, args.dump_text);
+    }
+
+    if args.tui {
+        run_tui(Arc::clone(&emu))?;
+        return shut_down(&emu, args.dump_text);
+    }
+
+    run_gui(emu, &args)
+}
+
+#[cfg(not(feature = "tui"))]
+fn run_tui(_emu: Arc<Mutex<Emulator>>) -> Result<()> {
+    bail!("built without the `tui` feature");
+}
+
+#[cfg(feature = "tui")]
+fn run_tui(emu: Arc<Mutex<Emulator>>) -> Result<()> {
+    apple_ii_emulator::tui::run(emu)
+}
+
+#[cfg(not(feature = "gui"))]
+fn run_gui(_emu: Arc<Mutex<Emulator>>, _args: &Args) -> Result<()> {
+    bail!("built without the `gui` feature; please use --headless");
+}
+
+#[cfg(feature = "gui")]
+fn run_gui(emu: Arc<Mutex<Emulator>>, args: &Args) -> Result<()> {
     let emu1 = Arc::clone(&emu);
-    thread::spawn(move || match run_debugger(emu1) {
-        Ok(()) => (),
-        Err(e) => eprintln!("\n{e}"),
+    let dump_text = args.dump_text;
+    thread::spawn(move || {
+        match run_debugger(Arc::clone(&emu1)) {
+            Ok(()) => (),
+            Err(e) => {
+                // Keep the window open. (E.g. if stdin was closed.)
+                eprintln!("\n{e}");
+                return;
+            }
+        }
+
+        // The user typed `quit`.
+        if let Err(e) = shut_down(&emu1, dump_text) {
+            eprintln!("{e}");
+        }
+        std::process::exit(0);
     });
 
     // Re-draw the screen at 60 Hz. This isn't the "right" way to do it, but
@@ -102,9 +280,36 @@ fn main() -> Result<()> {
         }
     });
 
-    let mut gui = Gui::new(emu);
+    let effects = Effects {
+        scanlines: args.scanlines,
+        persistence: args.persistence,
+    };
+    let mut gui = Gui::new(Arc::clone(&emu), effects);
     event_loop.run_app(&mut gui)?;
 
+    shut_down(&emu, args.dump_text)
+}
+
+/// Finish up any recording, etc.
+fn shut_down(emu: &Mutex<Emulator>, dump_text: bool) -> Result<()> {
+    let mut emu = emu.lock().unwrap();
+
+    if let Some(frames) = emu.stop_recording()? {
+        eprintln!("recorded {frames} frames");
+    }
+    if let Some(n) = emu.stop_input_recording()? {
+        eprintln!("recorded {n} inputs (RAM hash {:016x})", emu.ram_hash());
+    }
+    if let Some(n) = emu.stop_trace()? {
+        eprintln!("traced {n} instructions");
+    }
+
+    if dump_text {
+        for row in emu.screen_text() {
+            println!("{row}");
+        }
+    }
+
     Ok(())
 }
 
@@ -125,6 +330,9 @@ fn run_debugger(emu: Arc<Mutex<Emulator>>) -> Result<()> {
         print!("> ");
         io::stdout().flush()?;
         let line = lines.next().context("EOF on stdin")??;
+        if matches!(line.trim(), "q" | "quit") {
+            return Ok(());
+        }
 
         match parse_line(&line) {
             Ok(cmd) => emu.lock().unwrap().control(cmd),
@@ -138,7 +346,7 @@ fn run_debugger(emu: Arc<Mutex<Emulator>>) -> Result<()> {
 fn parse_line(mut line: &str) -> Result<Command> {
     line = line.trim();
     if line.is_empty() {
-        return Ok(Command::Step);
+        return Ok(Command::Step { count: 1 });
     }
     line.parse()
 }
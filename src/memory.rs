@@ -1,21 +1,32 @@
 mod io;
 mod rom;
+pub mod watchpoints;
 
 use std::{
     io::{self as std_io, Read},
-    mem,
+    ops::Range,
+    sync::Arc,
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use io::{Io, SoftSwitch};
 use rom::Rom;
+use watchpoints::{WatchHit, Watchpoint, Watchpoints};
 
-use crate::display::{color::Color, gr, hgr, text};
+use crate::{
+    display::{self, Frame, Mode, Monitor, VideoRam},
+    save_state::Sections,
+    speaker::Speaker,
+    video::{DirtyPages, Video},
+};
 
 /// Everything in the memory address space (including RAM, ROM, and I/O).
+#[derive(Clone)]
 pub struct AddressSpace {
     /// $0000..$c000
     main_ram: Box<[u8; 0xc000]>,
+    /// $0000..$c000, in the extended 80-column card.
+    aux_ram: Box<[u8; 0xc000]>,
     /// $c000..$d000
     io: Io,
     /// $d000..=$ffff
@@ -27,6 +38,11 @@ pub struct AddressSpace {
     /// Language card RAM, bank 2:
     /// $d000..$e000
     lc_bank_2: Box<[u8; 0x1000]>,
+
+    video: Video,
+    dirty: DirtyPages,
+    speaker: Speaker,
+    watchpoints: Watchpoints,
 }
 
 impl AddressSpace {
@@ -36,10 +52,15 @@ impl AddressSpace {
 
         Self {
             main_ram,
+            aux_ram: Box::new([0u8; 0xc000]),
             io: Io::new(),
             rom: Rom::new(),
             lc_ram: Box::new([0u8; 0x3000]),
             lc_bank_2: Box::new([0u8; 0x1000]),
+            video: Video::new(),
+            dirty: DirtyPages::new(),
+            speaker: Speaker::new(),
+            watchpoints: Watchpoints::default(),
         }
     }
 
@@ -83,10 +104,15 @@ impl AddressSpace {
         Ok((
             Self {
                 main_ram,
+                aux_ram: Box::new([0u8; 0xc000]),
                 io: Io::new(),
                 rom: Rom::new(),
                 lc_ram: Box::new([0u8; 0x3000]),
                 lc_bank_2: Box::new([0u8; 0x1000]),
+                video: Video::new(),
+                dirty: DirtyPages::new(),
+                speaker: Speaker::new(),
+                watchpoints: Watchpoints::default(),
             },
             start_addr.unwrap(),
         ))
@@ -108,9 +134,33 @@ impl AddressSpace {
     }
 
     pub fn read(&mut self, addr: u16) -> u8 {
+        let value = self.read_unwatched(addr);
+        if !self.watchpoints.is_empty() {
+            self.watchpoints.check_read(addr, value);
+        }
+        value
+    }
+
+    /// For the CPU fetching instructions. Watchpoints are only for data, so
+    /// running code in a watched range doesn't trigger them.
+    pub fn read_unwatched(&mut self, addr: u16) -> u8 {
         match addr {
+            0x0000..=0xbfff if self.is_aux(addr, self.io.soft_switch(SoftSwitch::RamRd)) => {
+                self.aux_ram[addr as usize]
+            }
             0x0000..=0xbfff => self.main_ram[addr as usize],
-            0xc000..=0xcfff => self.io.read(addr),
+
+            // RDVBLBAR: the hibit is *low* during vertical blanking. Nothing
+            // drives the other bits, so they come from the floating bus.
+            0xc019 => (!self.video.in_vblank() as u8) << 7 | self.floating_bus() & 0x7f,
+
+            // Nothing drives the bus when accessing the speaker.
+            0xc030 => {
+                self.speaker.toggle();
+                self.floating_bus()
+            }
+
+            0xc000..=0xcfff => self.io.read(addr).unwrap_or_else(|| self.floating_bus()),
             0xd000..=0xffff if !self.io.soft_switch(SoftSwitch::Lcram) => self.rom.read(addr),
 
             0xd000..=0xdfff if self.io.soft_switch(SoftSwitch::Bnk2) => {
@@ -121,8 +171,24 @@ impl AddressSpace {
     }
 
     pub fn write(&mut self, addr: u16, value: u8) {
+        if !self.watchpoints.is_empty() {
+            let old = self.peek_for_write(addr);
+            self.watchpoints.check_write(addr, old, value);
+        }
+        self.write_unwatched(addr, value);
+    }
+
+    fn write_unwatched(&mut self, addr: u16, value: u8) {
         match addr {
-            0x0000..=0xbfff => self.main_ram[addr as usize] = value,
+            0x0000..=0xbfff if self.is_aux(addr, self.io.soft_switch(SoftSwitch::RamWrt)) => {
+                self.aux_ram[addr as usize] = value;
+                self.dirty.mark(addr, true);
+            }
+            0x0000..=0xbfff => {
+                self.main_ram[addr as usize] = value;
+                self.dirty.mark(addr, false);
+            }
+            0xc030 => self.speaker.toggle(),
             0xc000..=0xcfff => self.io.write(addr, value),
             0xd000..=0xffff if self.io.soft_switch(SoftSwitch::WriteProtect) => {
                 eprintln!(
@@ -138,49 +204,262 @@ impl AddressSpace {
         }
     }
 
-    pub fn display(&self) -> Vec<Vec<Color>> {
-        // todo: this logic is buggy:
-        // * if text and hires are both set, we use the wrong page (causing a panic)
-        // * if hires and mixed are both set, we use the hires page for text (oops)
-        // * maybe other stuff too?
-        // Maybe we shouldn't try to be so clever. Just write something that works :P
-
-        let page = match (
-            self.io.soft_switch(SoftSwitch::Hires),
-            self.io.soft_switch(SoftSwitch::Page2),
-        ) {
-            (false, false) => &self.main_ram[0x400..0x800],
-            (false, true) => &self.main_ram[0x800..0xc00],
-            (true, false) => &self.main_ram[0x2000..0x4000],
-            (true, true) => &self.main_ram[0x4000..0x6000],
+    /// Read without any side effects, e.g. for the debugger. There's no such
+    /// thing for I/O (except the ROM in $c100..$cfff), so that's `None`.
+    pub fn peek(&self, addr: u16) -> Option<u8> {
+        let value = match addr {
+            0x0000..=0xbfff if self.is_aux(addr, self.io.soft_switch(SoftSwitch::RamRd)) => {
+                self.aux_ram[addr as usize]
+            }
+            0x0000..=0xbfff => self.main_ram[addr as usize],
+            0xc000..=0xcfff => return self.io.peek_rom(addr),
+            0xd000..=0xffff if !self.io.soft_switch(SoftSwitch::Lcram) => self.rom.read(addr),
+            0xd000..=0xdfff if self.io.soft_switch(SoftSwitch::Bnk2) => {
+                self.lc_bank_2[addr as usize - 0xd000]
+            }
+            0xd000..=0xffff => self.lc_ram[addr as usize - 0xd000],
         };
+        Some(value)
+    }
 
-        if self.io.soft_switch(SoftSwitch::Text) {
-            return text::dots(page);
+    /// The opposite of `peek`: change the byte that reading `addr` would see,
+    /// without any side effects. This even works on the ROM in
+    /// $c100..$cfff. (But not the rest of the ROM, which is shared.)
+    pub fn patch(&mut self, addr: u16, value: u8) -> Result<()> {
+        match addr {
+            0x0000..=0xbfff => {
+                let aux = self.is_aux(addr, self.io.soft_switch(SoftSwitch::RamRd));
+                if aux {
+                    self.aux_ram[addr as usize] = value;
+                } else {
+                    self.main_ram[addr as usize] = value;
+                }
+                self.dirty.mark(addr, aux);
+            }
+            0xc000..=0xcfff => {
+                if !self.io.patch_rom(addr, value) {
+                    bail!("can't patch I/O: ${addr:04x}");
+                }
+            }
+            0xd000..=0xffff if !self.io.soft_switch(SoftSwitch::Lcram) => {
+                bail!("can't patch ROM: ${addr:04x} (switch in the language card RAM first)");
+            }
+            0xd000..=0xdfff if self.io.soft_switch(SoftSwitch::Bnk2) => {
+                self.lc_bank_2[addr as usize - 0xd000] = value;
+            }
+            0xd000..=0xffff => self.lc_ram[addr as usize - 0xd000] = value,
         }
+        Ok(())
+    }
 
-        let mut dots = if self.io.soft_switch(SoftSwitch::Hires) {
-            hgr::dots_color(page);
-            hgr::dots_bw(page) // swap these if you want B&W display
-        } else {
-            gr::dots(page)
+    /// The byte that writing to `addr` would overwrite. (Without any side
+    /// effects, so `None` for I/O.)
+    fn peek_for_write(&self, addr: u16) -> Option<u8> {
+        let value = match addr {
+            0x0000..=0xbfff if self.is_aux(addr, self.io.soft_switch(SoftSwitch::RamWrt)) => {
+                self.aux_ram[addr as usize]
+            }
+            0x0000..=0xbfff => self.main_ram[addr as usize],
+            0xc000..=0xcfff => return None,
+            0xd000..=0xdfff if self.io.soft_switch(SoftSwitch::Bnk2) => {
+                self.lc_bank_2[addr as usize - 0xd000]
+            }
+            0xd000..=0xffff => self.lc_ram[addr as usize - 0xd000],
         };
+        Some(value)
+    }
+
+    /// Returns whether it's now set (as opposed to cleared).
+    pub fn toggle_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        self.watchpoints.toggle(watchpoint)
+    }
+
+    /// The watchpoints that triggered since the last call.
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        self.watchpoints.take_hits()
+    }
+
+    /// E.g. to run without triggering any, then put them back.
+    pub fn take_watchpoints(&mut self) -> Watchpoints {
+        std::mem::take(&mut self.watchpoints)
+    }
+
+    pub fn set_watchpoints(&mut self, watchpoints: Watchpoints) {
+        self.watchpoints = watchpoints;
+    }
+
+    pub fn save_state(&self, sections: &mut Sections) {
+        sections.add(b"MAIN", self.main_ram.to_vec());
+        sections.add(b"AUX ", self.aux_ram.to_vec());
+
+        let mut lc = self.lc_ram.to_vec();
+        lc.extend(self.lc_bank_2.iter());
+        sections.add(b"LC  ", lc);
+
+        self.io.save_state(sections);
+        self.video.save_state(sections);
+        self.speaker.save_state(sections);
+    }
+
+    pub fn load_state(&mut self, sections: &Sections) -> Result<()> {
+        let main = sections.get(b"MAIN")?.bytes(0xc000)?;
+        let aux = sections.get(b"AUX ")?.bytes(0xc000)?;
+        let mut lc = sections.get(b"LC  ")?;
+        let lc_ram = lc.bytes(0x3000)?;
+        let lc_bank_2 = lc.bytes(0x1000)?;
+
+        self.main_ram.copy_from_slice(main);
+        self.aux_ram.copy_from_slice(aux);
+        self.lc_ram.copy_from_slice(lc_ram);
+        self.lc_bank_2.copy_from_slice(lc_bank_2);
+
+        self.io.load_state(sections)?;
+        self.video.load_state(sections)?;
+        self.speaker.load_state(sections)?;
+        Ok(())
+    }
+
+    /// Should this access go to aux memory instead of main memory?
+    ///
+    /// `ram_rd_wrt` is the value of either RAMRD or RAMWRT, depending on
+    /// whether this is a read or a write.
+    fn is_aux(&self, addr: u16, ram_rd_wrt: bool) -> bool {
+        let switch = |s| self.io.soft_switch(s);
 
-        if self.io.soft_switch(SoftSwitch::Mixed) {
-            let mut text_dots = text::dots(page);
-            for y in 20 * text::CELL_H..24 * text::CELL_H {
-                dots[y] = mem::take(&mut text_dots[y]);
+        match addr {
+            // todo: ALTZP should also switch the language card RAM.
+            0x0000..=0x01ff => switch(SoftSwitch::Altzp),
+
+            // When 80STORE is on, PAGE2 selects between main and aux memory
+            // for the display pages, and overrides RAMRD/RAMWRT.
+            0x0400..=0x07ff if switch(SoftSwitch::_80Store) => switch(SoftSwitch::Page2),
+            0x2000..=0x3fff if switch(SoftSwitch::_80Store) && switch(SoftSwitch::Hires) => {
+                switch(SoftSwitch::Page2)
             }
+
+            _ => ram_rd_wrt,
+        }
+    }
+
+    /// Render the screen, based on what's in memory right now.
+    pub fn display(&self) -> Frame {
+        display::frame(&self.video_ram(), self.display_mode(), self.monitor())
+    }
+
+    /// The bytes on the current text page, one row at a time.
+    pub fn text_page(&self) -> Vec<Vec<u8>> {
+        display::text_page(&self.video_ram(), self.display_mode())
+    }
+
+    /// A hash of all the RAM (main, aux, and language card), to check that
+    /// two runs ended up in the same place. (FNV-1a.)
+    pub fn ram_hash(&self) -> u64 {
+        let ram = [
+            &self.main_ram[..],
+            &self.aux_ram[..],
+            &self.lc_ram[..],
+            &self.lc_bank_2[..],
+        ];
+
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        for b in ram.into_iter().flatten() {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x100_0000_01b3);
+        }
+        hash
+    }
+
+    /// The most recent frame that the beam finished drawing.
+    pub fn video_frame(&self) -> Option<&Arc<Frame>> {
+        self.video.frame()
+    }
+
+    /// See `Video::frame_number`.
+    pub fn video_frame_number(&self) -> u64 {
+        self.video.frame_number()
+    }
+
+    /// See `Video::lines_redrawn_since`.
+    pub fn video_lines_redrawn_since(&self, since: u64) -> Range<usize> {
+        self.video.lines_redrawn_since(since)
+    }
+
+    pub fn monitor(&self) -> Monitor {
+        self.video.monitor()
+    }
+
+    pub fn set_monitor(&mut self, monitor: Monitor) {
+        self.video.set_monitor(monitor);
+    }
+
+    /// Let some time pass (measured in CPU cycles), so the video beam can
+    /// move along.
+    ///
+    /// Returns true if the beam just finished a frame.
+    pub fn tick(&mut self, cycles: u8) -> bool {
+        let ram = VideoRam {
+            main: &self.main_ram[..],
+            aux: &self.aux_ram[..],
+        };
+        let mode = self.display_mode();
+        self.speaker.tick(cycles);
+        self.io.keyboard.tick(cycles);
+        self.video.tick(cycles, &ram, mode, &self.dirty)
+    }
+
+    /// When the speaker was toggled, since the last call. See `Speaker`.
+    pub fn take_speaker_toggles(&mut self) -> Vec<u64> {
+        self.speaker.take_toggles()
+    }
+
+    /// The byte the video hardware is reading from memory, right now.
+    fn floating_bus(&self) -> u8 {
+        let addr = self.video.scanner_address(self.display_mode());
+        self.main_ram[addr as usize]
+    }
+
+    pub fn video_ram(&self) -> VideoRam<'_> {
+        VideoRam {
+            main: &self.main_ram[..],
+            aux: &self.aux_ram[..],
         }
+    }
+
+    pub fn display_mode(&self) -> Mode {
+        let switch = |s| self.io.soft_switch(s);
 
-        dots
+        Mode {
+            text: switch(SoftSwitch::Text),
+            mixed: switch(SoftSwitch::Mixed),
+            hires: switch(SoftSwitch::Hires),
+            page2: switch(SoftSwitch::Page2) && !switch(SoftSwitch::_80Store),
+            col80: switch(SoftSwitch::_80Col),
+            dhires: switch(SoftSwitch::Dhires),
+        }
     }
 
     pub fn key_down(&mut self, ascii_code: u8) {
-        self.io.key_down(ascii_code);
+        self.io.keyboard.key_down(ascii_code);
+    }
+
+    pub fn key_up(&mut self, ascii_code: u8) {
+        self.io.keyboard.key_up(ascii_code);
     }
 
     pub fn all_keys_up(&mut self) {
-        self.io.all_keys_up();
+        self.io.keyboard.all_keys_up();
+    }
+
+    pub fn set_apple_keys(&mut self, open_apple: bool, solid_apple: bool) {
+        self.io.keyboard.open_apple = open_apple;
+        self.io.keyboard.solid_apple = solid_apple;
+    }
+
+    pub fn type_text(&mut self, text: &str) {
+        self.io.keyboard.type_text(text);
+    }
+
+    pub fn reset(&mut self) {
+        self.io.reset();
     }
 }
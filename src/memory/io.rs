@@ -1,9 +1,15 @@
+mod keyboard;
 mod soft_switches;
 
+use anyhow::Result;
+use keyboard::Keyboard;
 pub use soft_switches::SoftSwitch;
 use soft_switches::SoftSwitches;
 
+use crate::save_state::Sections;
+
 /// $c000..$d000
+#[derive(Clone)]
 pub struct Io {
     /// $c100..$c400
     c100_rom: Box<[u8; 0x300]>,
@@ -12,12 +18,8 @@ pub struct Io {
     /// $c800..=$cffe
     c800_rom: Box<[u8; 0x800 - 1]>,
 
-    /// $c000 (without hibit)
-    most_recent_key: u8,
-    /// $c000 hibit
-    strobe_bit: bool,
-    /// $c010 hibit
-    any_key_down: bool,
+    /// $c000, $c010, $c061, $c062
+    pub keyboard: Keyboard,
 
     /// $c000..$c100
     switches: SoftSwitches,
@@ -33,9 +35,7 @@ impl Io {
             self_test_rom: Box::new(*self_test),
             c800_rom: Box::new(rom[0x300..].try_into().unwrap()),
 
-            most_recent_key: 0u8,
-            strobe_bit: false,
-            any_key_down: false,
+            keyboard: Keyboard::new(),
 
             switches: SoftSwitches::new(),
         }
@@ -45,46 +45,42 @@ impl Io {
         self.switches.is_set(switch)
     }
 
-    pub fn key_down(&mut self, ascii_code: u8) {
-        assert!(ascii_code < 0x80);
-        self.most_recent_key = ascii_code;
-        self.strobe_bit = true;
-        self.any_key_down = true;
+    pub fn save_state(&self, sections: &mut Sections) {
+        self.switches.save_state(sections);
+        self.keyboard.save_state(sections);
     }
 
-    pub fn all_keys_up(&mut self) {
-        self.any_key_down = false;
+    pub fn load_state(&mut self, sections: &Sections) -> Result<()> {
+        self.switches.load_state(sections)?;
+        self.keyboard.load_state(sections)
     }
 
-    pub fn read(&mut self, addr: u16) -> u8 {
-        match addr {
-            0xc000 => {
-                let mut byte = self.most_recent_key;
-                if self.strobe_bit {
-                    byte |= 0x80;
-                }
-                byte
-            }
-            0xc010 => {
-                self.strobe_bit = false;
-                if self.any_key_down {
-                    0x80
-                } else {
-                    0
-                }
-            }
+    /// Hardware reset: switch back to main memory, ROM, and 40 columns.
+    pub fn reset(&mut self) {
+        self.switches.reset();
+    }
+
+    /// Returns `None` if nothing drives the data bus, in which case the CPU
+    /// sees whatever the video hardware happens to be reading. (This is
+    /// called the "floating bus".)
+    // (We special-case a few addresses, before handling the whole range.)
+    #[allow(clippy::match_overlapping_arm)]
+    pub fn read(&mut self, addr: u16) -> Option<u8> {
+        let byte = match addr {
+            0xc000 => self.keyboard.data(),
+            0xc010 => self.keyboard.clear_strobe(),
+
+            0xc061 => button(self.keyboard.open_apple),
+            0xc062 => button(self.keyboard.solid_apple),
 
             // Hacks to make these programs not crash.
             // (todo: presumably these are soft switches?)
             // * tron
-            0xc015 | 0xc058 | 0xc05a | 0xc05d | 0xc062 | 0xc061 | 0xc030 => 0,
+            0xc015 | 0xc058 | 0xc05a | 0xc05d => 0,
             // * self-test rom
-            0xc013 | 0xc014 | 0xc017 => 0,
+            0xc017 => 0,
 
-            // todo: bank select
-            0xc081 | 0xc080 | 0xc082 => 0,
-
-            0xc000..=0xc0ff => self.switches.read(addr),
+            0xc000..=0xc0ff => return self.switches.read(addr),
 
             0xcfff => 0, // todo: what's this byte supposed to be?
 
@@ -93,21 +89,93 @@ impl Io {
             0xc800..=0xcffe => self.c800_rom[addr as usize - 0xc800],
 
             _ => panic!("${addr:04x}"),
-        }
+        };
+        Some(byte)
     }
 
     pub fn write(&mut self, addr: u16, value: u8) {
         match addr {
-            0xc010 => self.strobe_bit = false,
+            0xc010 => {
+                self.keyboard.clear_strobe();
+            }
 
             // Hacks to make the tron program not crash:
             0xc007 | 0xc006 => (),
             // * self-test rom
-            0xc003 | 0xc002 | 0xc005 | 0xc004 | 0xc00b | 0xc00a => (),
+            0xc00b | 0xc00a => (),
 
             0xc000..=0xc0ff => self.switches.write(addr),
 
+            // (ROM, so nothing happens.)
+            0xc100..=0xcffe => (),
+
             _ => panic!("${addr:04x} ${value:02x}"),
         }
     }
 }
+
+impl Io {
+    /// The ROM's byte at `addr`, or `None` if it's not ROM.
+    pub fn peek_rom(&self, addr: u16) -> Option<u8> {
+        let byte = match addr {
+            0xc100..=0xc3ff => self.c100_rom[addr as usize - 0xc100],
+            0xc400..=0xc7ff => self.self_test_rom[addr as usize - 0xc400],
+            0xc800..=0xcffe => self.c800_rom[addr as usize - 0xc800],
+            _ => return None,
+        };
+        Some(byte)
+    }
+
+    /// Change a byte of ROM, e.g. to skip part of the self-test. Returns
+    /// false if it's not ROM.
+    pub fn patch_rom(&mut self, addr: u16, value: u8) -> bool {
+        let byte = match addr {
+            0xc100..=0xc3ff => &mut self.c100_rom[addr as usize - 0xc100],
+            0xc400..=0xc7ff => &mut self.self_test_rom[addr as usize - 0xc400],
+            0xc800..=0xcffe => &mut self.c800_rom[addr as usize - 0xc800],
+            _ => return false,
+        };
+        *byte = value;
+        true
+    }
+}
+
+/// Pushbuttons read as the hibit.
+fn button(pressed: bool) -> u8 {
+    if pressed {
+        0x80
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn any_switch_can_be_accessed() {
+        let mut io = Io::new();
+        for addr in 0xc000..=0xc0ff {
+            io.read(addr);
+            io.write(addr, 0);
+        }
+        // Slot I/O floats.
+        assert_eq!(io.read(0xc090), None);
+        assert_eq!(io.read(0xc0ff), None);
+    }
+
+    #[test]
+    fn reading_bank_select_switches() {
+        let mut io = Io::new();
+        // Read RAM, bank 2.
+        assert_eq!(io.read(0xc080), None);
+        assert!(io.soft_switch(SoftSwitch::Lcram));
+        assert!(io.soft_switch(SoftSwitch::Bnk2));
+
+        // Back to ROM.
+        assert_eq!(io.read(0xc082), None);
+        assert!(!io.soft_switch(SoftSwitch::Lcram));
+        assert!(io.soft_switch(SoftSwitch::Bnk2));
+    }
+}
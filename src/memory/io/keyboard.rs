@@ -0,0 +1,240 @@
+//! The keyboard. On the //e, an AY-5-3600 chip scans the keys, converts them
+//! to ASCII, and handles auto-repeat.
+
+use std::collections::VecDeque;
+
+use anyhow::Result;
+
+use crate::{cpu::CLOCK_HZ, save_state::Sections};
+
+/// How long a key needs to be held before it starts repeating.
+const REPEAT_DELAY: u64 = CLOCK_HZ / 2;
+/// Then it repeats about 15 times a second.
+const REPEAT_INTERVAL: u64 = CLOCK_HZ / 15;
+
+#[derive(Clone)]
+pub struct Keyboard {
+    /// $c000 (without hibit)
+    most_recent_key: u8,
+    /// $c000 hibit
+    strobe: bool,
+
+    /// Keys that are being held down right now. (For $c010's hibit.)
+    held: Vec<u8>,
+    /// The key that'll auto-repeat, if it's held long enough. (Only the most
+    /// recently pressed key repeats.)
+    repeating: Option<u8>,
+    /// Cycles until the next repeat.
+    repeat_in: u64,
+
+    /// Keys that have been "typed", but not read yet. We feed them in one at
+    /// a time: whenever the program checks for a key after clearing the
+    /// strobe.
+    type_ahead: VecDeque<u8>,
+
+    /// These are wired to the game port's pushbuttons 0 and 1 ($c061 and
+    /// $c062).
+    pub open_apple: bool,
+    pub solid_apple: bool,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self {
+            most_recent_key: 0,
+            strobe: false,
+            held: vec![],
+            repeating: None,
+            repeat_in: 0,
+            type_ahead: VecDeque::new(),
+            open_apple: false,
+            solid_apple: false,
+        }
+    }
+
+    pub fn key_down(&mut self, ascii_code: u8) {
+        assert!(ascii_code < 0x80);
+        self.most_recent_key = ascii_code;
+        self.strobe = true;
+
+        self.held.push(ascii_code);
+        self.repeating = Some(ascii_code);
+        self.repeat_in = REPEAT_DELAY;
+    }
+
+    pub fn key_up(&mut self, ascii_code: u8) {
+        if let Some(i) = self.held.iter().position(|&k| k == ascii_code) {
+            self.held.remove(i);
+        }
+        if self.repeating == Some(ascii_code) {
+            self.repeating = None;
+        }
+    }
+
+    pub fn all_keys_up(&mut self) {
+        self.held.clear();
+        self.repeating = None;
+    }
+
+    /// Queue up some text to be typed, one key at a time.
+    pub fn type_text(&mut self, text: &str) {
+        self.type_ahead.extend(translate_for_typing(text));
+    }
+
+    /// Let time pass, for auto-repeat.
+    pub fn tick(&mut self, cycles: u8) {
+        let Some(key) = self.repeating else {
+            return;
+        };
+
+        match self.repeat_in.checked_sub(cycles as u64) {
+            Some(n) if n > 0 => self.repeat_in = n,
+            _ => {
+                self.most_recent_key = key;
+                self.strobe = true;
+                self.repeat_in = REPEAT_INTERVAL;
+            }
+        }
+    }
+
+    /// $c000: the most recent key, with the hibit set if it hasn't been read
+    /// yet.
+    pub fn data(&mut self) -> u8 {
+        // (If we loaded the next key as soon as the strobe got cleared, the
+        // program might miss it. E.g. the RESET routine clears the strobe,
+        // without waiting for a key.)
+        if !self.strobe {
+            self.next_typed_key();
+        }
+
+        if self.strobe {
+            self.most_recent_key | 0x80
+        } else {
+            self.most_recent_key
+        }
+    }
+
+    /// $c010: clear the strobe. Reading it also tells you if any key is being
+    /// held down.
+    pub fn clear_strobe(&mut self) -> u8 {
+        self.strobe = false;
+        if self.held.is_empty() {
+            0
+        } else {
+            0x80
+        }
+    }
+
+    /// The latch, plus (in a separate section) everything else. Rewinding
+    /// needs all of it, to replay exactly what happened.
+    pub fn save_state(&self, sections: &mut Sections) {
+        sections.add(b"KBD ", vec![self.most_recent_key, self.strobe as u8]);
+
+        let mut keys = vec![self.held.len() as u8];
+        keys.extend(&self.held);
+        keys.push(self.repeating.unwrap_or(0xff));
+        keys.extend(self.repeat_in.to_le_bytes());
+        keys.extend((self.type_ahead.len() as u32).to_le_bytes());
+        keys.extend(&self.type_ahead);
+        keys.push(self.open_apple as u8);
+        keys.push(self.solid_apple as u8);
+        sections.add(b"KEYS", keys);
+    }
+
+    pub fn load_state(&mut self, sections: &Sections) -> Result<()> {
+        let mut r = sections.get(b"KBD ")?;
+        self.most_recent_key = r.u8()? & 0x7f;
+        self.strobe = r.bool()?;
+
+        let Some(mut r) = sections.find(b"KEYS") else {
+            *self = Self {
+                most_recent_key: self.most_recent_key,
+                strobe: self.strobe,
+                ..Self::new()
+            };
+            return Ok(());
+        };
+        let n = r.u8()? as usize;
+        self.held = r.bytes(n)?.iter().map(|k| k & 0x7f).collect();
+        self.repeating = match r.u8()? {
+            0xff => None,
+            key => Some(key & 0x7f),
+        };
+        self.repeat_in = r.u64()?;
+        let n = r.u32()? as usize;
+        self.type_ahead = r.bytes(n)?.iter().map(|k| k & 0x7f).collect();
+        self.open_apple = r.bool()?;
+        self.solid_apple = r.bool()?;
+        Ok(())
+    }
+
+    fn next_typed_key(&mut self) {
+        if let Some(key) = self.type_ahead.pop_front() {
+            assert!(key < 0x80);
+            self.most_recent_key = key;
+            self.strobe = true;
+        }
+    }
+}
+
+/// Convert host text into keys that make sense on an unenhanced //e: line
+/// endings become Return, and lowercase becomes uppercase (since e.g.
+/// Applesoft doesn't understand lowercase commands). Anything that isn't
+/// ASCII gets dropped.
+fn translate_for_typing(text: &str) -> Vec<u8> {
+    let text = text.replace("\r\n", "\r").replace('\n', "\r");
+    text.bytes()
+        .filter(|b| b.is_ascii())
+        .map(|b| b.to_ascii_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typing_translates_text() {
+        let keys = translate_for_typing("10 print \"hé\"\r\n20 end\n");
+        assert_eq!(keys, b"10 PRINT \"H\"\r20 END\r");
+    }
+
+    #[test]
+    fn typing_waits_for_strobe() {
+        let mut keyboard = Keyboard::new();
+        keyboard.type_text("ab");
+
+        assert_eq!(keyboard.data(), b'A' | 0x80);
+        assert_eq!(keyboard.data(), b'A' | 0x80);
+        keyboard.clear_strobe();
+        assert_eq!(keyboard.data(), b'B' | 0x80);
+        keyboard.clear_strobe();
+        assert_eq!(keyboard.data(), b'B');
+    }
+
+    #[test]
+    fn held_key_repeats() {
+        let mut keyboard = Keyboard::new();
+        keyboard.key_down(b'X');
+        keyboard.clear_strobe();
+
+        let mut strobes = 0;
+        for _ in 0..CLOCK_HZ / 4 {
+            keyboard.tick(4);
+            if keyboard.data() & 0x80 != 0 {
+                strobes += 1;
+                assert_eq!(keyboard.clear_strobe(), 0x80);
+            }
+        }
+        // Held for 1 second: half a second of delay, then half a second of
+        // repeating.
+        assert_eq!(strobes, 1 + 15 / 2);
+
+        keyboard.key_up(b'X');
+        assert_eq!(keyboard.clear_strobe(), 0);
+        for _ in 0..CLOCK_HZ {
+            keyboard.tick(1);
+        }
+        assert_eq!(keyboard.data(), b'X');
+    }
+}
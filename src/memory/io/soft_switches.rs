@@ -1,8 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-#[derive(Debug)]
+use anyhow::Result;
+
+use crate::save_state::Sections;
+
+#[derive(Debug, Clone)]
 pub struct SoftSwitches {
     states: HashMap<SoftSwitch, bool>,
+    /// Unknown switches we've already warned about (by the low byte of the
+    /// address), so programs that poll one don't flood stderr.
+    warned: HashSet<u8>,
 }
 
 /// See Apple //e Technical Reference Manual, Appendix F: Frequently Used
@@ -27,6 +34,30 @@ pub enum SoftSwitch {
     Lcram,
     /// todo: not quite sure what this does
     Altzp,
+    /// Read $0200..$c000 from aux memory.
+    RamRd,
+    /// Write $0200..$c000 to aux memory.
+    RamWrt,
+}
+
+impl SoftSwitch {
+    const ALL: [SoftSwitch; 15] = [
+        SoftSwitch::Altchar,
+        SoftSwitch::_80Col,
+        SoftSwitch::_80Store,
+        SoftSwitch::Page2,
+        SoftSwitch::Text,
+        SoftSwitch::Mixed,
+        SoftSwitch::Hires,
+        SoftSwitch::IouEnable,
+        SoftSwitch::Dhires,
+        SoftSwitch::WriteProtect,
+        SoftSwitch::Bnk2,
+        SoftSwitch::Lcram,
+        SoftSwitch::Altzp,
+        SoftSwitch::RamRd,
+        SoftSwitch::RamWrt,
+    ];
 }
 
 impl SoftSwitches {
@@ -34,17 +65,66 @@ impl SoftSwitches {
         // todo: do any switches have default values other than false ?
         Self {
             states: HashMap::new(),
+            warned: HashSet::new(),
+        }
+    }
+
+    /// What the RESET line does. The display switches stay as they are (it's
+    /// up to the RESET routine to switch back to text mode). But the memory
+    /// switches all go back to main memory and ROM, and the language card
+    /// goes back to writing to bank 2.
+    pub fn reset(&mut self) {
+        use SoftSwitch::*;
+
+        for switch in [_80Store, RamRd, RamWrt, Altzp, _80Col, Altchar, Lcram] {
+            self.states.insert(switch, false);
+        }
+        self.states.insert(Bnk2, true);
+        self.states.insert(WriteProtect, false);
+    }
+
+    /// Each switch is stored by name, followed by its value.
+    pub fn save_state(&self, sections: &mut Sections) {
+        let mut data = vec![];
+        for switch in SoftSwitch::ALL {
+            let name = format!("{switch:?}");
+            data.push(name.len() as u8);
+            data.extend(name.bytes());
+            data.push(self.is_set(switch) as u8);
+        }
+        sections.add(b"SWCH", data);
+    }
+
+    pub fn load_state(&mut self, sections: &Sections) -> Result<()> {
+        let mut r = sections.get(b"SWCH")?;
+
+        let mut states = HashMap::new();
+        while !r.is_empty() {
+            let len = r.u8()? as usize;
+            let name = String::from_utf8_lossy(r.bytes(len)?).into_owned();
+            let value = r.bool()?;
+
+            match SoftSwitch::ALL.into_iter().find(|s| format!("{s:?}") == name) {
+                Some(switch) => {
+                    states.insert(switch, value);
+                }
+                None => eprintln!("warning: unknown soft switch in save state: {name}"),
+            }
         }
+        self.states = states;
+        Ok(())
     }
 
     pub fn is_set(&self, switch: SoftSwitch) -> bool {
         self.states.get(&switch).copied().unwrap_or(false)
     }
 
-    pub fn read(&mut self, addr: u16) -> u8 {
-        match self.access(addr, AccessType::Read) {
-            Some(true) => 0x80,
-            Some(false) | None => 0,
+    /// Returns `None` if nothing drives the data bus. (E.g. for switches
+    /// that get flipped by reading them.)
+    pub fn read(&mut self, addr: u16) -> Option<u8> {
+        match self.access(addr, AccessType::Read)? {
+            true => Some(0x80),
+            false => Some(0),
         }
     }
 
@@ -57,12 +137,19 @@ impl SoftSwitches {
         let [lo, hi] = addr.to_le_bytes();
         assert_eq!(hi, 0xc0);
 
-        if lo & 0x80 != 0 {
-            self.bank_select(lo, rw);
+        // $c084..$c088 and $c08c..$c090 mirror the four switches below them.
+        // Above $c090 is slot I/O, which we don't emulate.
+        if let 0x80..=0x8f = lo {
+            self.bank_select(lo & !0b_0100, rw);
             return None;
         }
 
-        let (switch, op) = soft_switch_info(lo, rw);
+        let Some((switch, op)) = soft_switch_info(lo, rw) else {
+            if self.warned.insert(lo) {
+                eprintln!("warning: unknown soft switch: $c0{lo:02x} ({rw:?})");
+            }
+            return None;
+        };
         match op {
             Operation::Clear => self.states.insert(switch, false),
             Operation::Set => self.states.insert(switch, true),
@@ -94,7 +181,7 @@ impl SoftSwitches {
         self.states.insert(SoftSwitch::WriteProtect, !write_enable);
 
         // If the two lowest bits are the same, read RAM. Otherwise, read ROM.
-        let read_ram = lo & 0b_0010 == lo & 0b_0001;
+        let read_ram = (lo & 0b_0010 != 0) == (lo & 0b_0001 != 0);
         self.states.insert(SoftSwitch::Lcram, read_ram);
     }
 }
@@ -111,13 +198,13 @@ enum Operation {
     Query,
 }
 
-fn soft_switch_info(lo: u8, rw: AccessType) -> (SoftSwitch, Operation) {
+fn soft_switch_info(lo: u8, rw: AccessType) -> Option<(SoftSwitch, Operation)> {
     use AccessType::*;
     use Operation::*;
     use SoftSwitch::*;
 
     // This information is from tables 2-10 and 4-6 in the TRM.
-    match (lo, rw) {
+    let info = match (lo, rw) {
         //
         // Table 2-10. Display Soft Switches
         //
@@ -155,8 +242,10 @@ fn soft_switch_info(lo: u8, rw: AccessType) -> (SoftSwitch, Operation) {
         (0x7f, Write) => (IouEnable, Set),
         (0x7e, Read) => (IouEnable, Query),
 
-        (0x5e, Read | Write) => (Dhires, Clear),
-        (0x5f, Read | Write) => (Dhires, Set),
+        // NOTE: unlike the other pairs, the lower address turns this one on.
+        // (It's annunciator 3, which is active-low.)
+        (0x5e, Read | Write) => (Dhires, Set),
+        (0x5f, Read | Write) => (Dhires, Clear),
         (0x7f, Read) => (Dhires, Query),
 
         //
@@ -169,6 +258,35 @@ fn soft_switch_info(lo: u8, rw: AccessType) -> (SoftSwitch, Operation) {
         (0x09, Write) => (Altzp, Set),
         (0x16, Read) => (Altzp, Query),
 
-        _ => panic!("$c0{lo:02x}"),
+        (0x02, Write) => (RamRd, Clear),
+        (0x03, Write) => (RamRd, Set),
+        (0x13, Read) => (RamRd, Query),
+
+        (0x04, Write) => (RamWrt, Clear),
+        (0x05, Write) => (RamWrt, Set),
+        (0x14, Read) => (RamWrt, Query),
+
+        _ => return None,
+    };
+    Some(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bank_select_mirrors() {
+        let mut switches = SoftSwitches::new();
+        switches.read(0xc08b);
+        assert!(!switches.is_set(SoftSwitch::Bnk2));
+        assert!(switches.is_set(SoftSwitch::Lcram));
+        assert!(!switches.is_set(SoftSwitch::WriteProtect));
+
+        // Same as $c082: bank 2, read ROM, write protect.
+        switches.read(0xc086);
+        assert!(switches.is_set(SoftSwitch::Bnk2));
+        assert!(!switches.is_set(SoftSwitch::Lcram));
+        assert!(switches.is_set(SoftSwitch::WriteProtect));
     }
 }
@@ -1,4 +1,5 @@
 /// $d000..=$ffff
+#[derive(Clone)]
 pub struct Rom {
     /// $d000..$f800
     applesoft: &'static [u8; 0x2800],
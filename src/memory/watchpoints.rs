@@ -0,0 +1,161 @@
+//! Watchpoints: breaking when the program reads or writes some memory.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    /// `watch`
+    Write,
+    /// `rwatch`
+    Read,
+    /// `awatch`: either one.
+    Access,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end_inclusive: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    fn matches(&self, addr: u16, write: bool) -> bool {
+        let kind = match self.kind {
+            WatchKind::Write => write,
+            WatchKind::Read => !write,
+            WatchKind::Access => true,
+        };
+        kind && (self.start..=self.end_inclusive).contains(&addr)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self.kind {
+            WatchKind::Write => "watchpoint",
+            WatchKind::Read => "read watchpoint",
+            WatchKind::Access => "access watchpoint",
+        };
+        if self.start == self.end_inclusive {
+            write!(f, "{name} ${:04x}", self.start)
+        } else {
+            write!(f, "{name} ${:04x}.${:04x}", self.start, self.end_inclusive)
+        }
+    }
+}
+
+/// An access that triggered a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchHit {
+    Read {
+        addr: u16,
+        value: u8,
+    },
+    Write {
+        addr: u16,
+        /// `None` for I/O, since there's no such thing as the old value (and
+        /// reading it could have side effects).
+        old: Option<u8>,
+        new: u8,
+    },
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WatchHit::Read { addr, value } => write!(f, "read ${addr:04x}: ${value:02x}"),
+            WatchHit::Write {
+                addr,
+                old: Some(old),
+                new,
+            } => write!(f, "wrote ${addr:04x}: ${old:02x} -> ${new:02x}"),
+            WatchHit::Write {
+                addr,
+                old: None,
+                new,
+            } => write!(f, "wrote ${addr:04x}: ${new:02x}"),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Watchpoints {
+    watchpoints: Vec<Watchpoint>,
+    hits: Vec<WatchHit>,
+}
+
+impl Watchpoints {
+    /// Returns whether it's now set (as opposed to cleared).
+    pub fn toggle(&mut self, watchpoint: Watchpoint) -> bool {
+        if let Some(i) = self.watchpoints.iter().position(|&w| w == watchpoint) {
+            self.watchpoints.remove(i);
+            false
+        } else {
+            self.watchpoints.push(watchpoint);
+            true
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    pub fn check_read(&mut self, addr: u16, value: u8) {
+        if self.watchpoints.iter().any(|w| w.matches(addr, false)) {
+            self.hits.push(WatchHit::Read { addr, value });
+        }
+    }
+
+    pub fn check_write(&mut self, addr: u16, old: Option<u8>, new: u8) {
+        if self.watchpoints.iter().any(|w| w.matches(addr, true)) {
+            self.hits.push(WatchHit::Write { addr, old, new });
+        }
+    }
+
+    pub fn take_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_and_kinds() {
+        let mut watchpoints = Watchpoints::default();
+        watchpoints.toggle(Watchpoint {
+            start: 0x00,
+            end_inclusive: 0xff,
+            kind: WatchKind::Write,
+        });
+        watchpoints.toggle(Watchpoint {
+            start: 0x300,
+            end_inclusive: 0x300,
+            kind: WatchKind::Read,
+        });
+
+        watchpoints.check_read(0x10, 1);
+        watchpoints.check_write(0x10, Some(1), 2);
+        watchpoints.check_write(0x100, Some(1), 2);
+        watchpoints.check_read(0x300, 3);
+        watchpoints.check_write(0x300, Some(3), 4);
+
+        assert_eq!(
+            watchpoints.take_hits(),
+            [
+                WatchHit::Write {
+                    addr: 0x10,
+                    old: Some(1),
+                    new: 2
+                },
+                WatchHit::Read {
+                    addr: 0x300,
+                    value: 3
+                },
+            ]
+        );
+        assert!(watchpoints.take_hits().is_empty());
+    }
+}
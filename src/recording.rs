@@ -0,0 +1,282 @@
+//! Recording the screen (and the speaker) to files.
+//!
+//! We capture a frame every time the beam finishes one, so recordings run at
+//! the emulated ~60 Hz, no matter how fast the emulator is actually running.
+//!
+//! The video is either a numbered sequence of PNGs, or a raw Y4M stream
+//! (which e.g. ffmpeg can read). The audio goes in a WAV file alongside.
+
+use std::{
+    fs::{self, File},
+    io::{prelude::*, BufWriter, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    cpu::CLOCK_HZ,
+    display::{self, Frame},
+    screenshot,
+    video::CYCLES_PER_FRAME,
+};
+
+const SAMPLE_RATE: u64 = 44_100;
+
+pub struct Recorder {
+    video: VideoOut,
+    audio: Wav,
+    num_frames: u64,
+}
+
+enum VideoOut {
+    /// A directory full of `frame-NNNNN.png`.
+    Png(PathBuf),
+    Y4m(BufWriter<File>),
+}
+
+impl Recorder {
+    /// If `path` ends in `.y4m`, record to a Y4M file. Otherwise, `path` is a
+    /// directory to fill with PNGs.
+    ///
+    /// The audio goes to `path` with the extension replaced by `.wav`. It
+    /// starts at cycle `start`, i.e. now.
+    pub fn new(path: impl AsRef<Path>, start: u64) -> Result<Self> {
+        let path = path.as_ref();
+
+        let video = if path.extension().is_some_and(|ext| ext == "y4m") {
+            let mut file = BufWriter::new(File::create(path)?);
+            // The pixels are much taller than they are wide: the screen is
+            // 560x192, but it's displayed at 4:3.
+            writeln!(
+                file,
+                "YUV4MPEG2 W{} H{} F{CLOCK_HZ}:{CYCLES_PER_FRAME} Ip A16:35 C444",
+                display::W,
+                display::H,
+            )?;
+            VideoOut::Y4m(file)
+        } else {
+            fs::create_dir_all(path)
+                .with_context(|| format!("couldn't create directory {}", path.display()))?;
+            VideoOut::Png(path.to_owned())
+        };
+
+        let audio = Wav::create(&path.with_extension("wav"), start)?;
+
+        Ok(Self {
+            video,
+            audio,
+            num_frames: 0,
+        })
+    }
+
+    /// Record a frame, plus the sound up to cycle `now`.
+    ///
+    /// `toggles` are the speaker toggles since the previous frame.
+    pub fn frame(&mut self, frame: &Frame, toggles: &[u64], now: u64) -> Result<()> {
+        match &mut self.video {
+            VideoOut::Png(dir) => {
+                // Always 2x, so every frame is the same size. (Native
+                // resolution would switch between 280 and 560 wide.)
+                let path = dir.join(format!("frame-{:05}.png", self.num_frames));
+                screenshot::save_png(frame, path, 2)?;
+            }
+            VideoOut::Y4m(file) => {
+                file.write_all(b"FRAME\n")?;
+                file.write_all(&yuv_planes(frame))?;
+            }
+        }
+        self.num_frames += 1;
+
+        self.audio.write(toggles, now)
+    }
+
+    /// Flush everything to disk.
+    pub fn finish(mut self) -> Result<u64> {
+        if let VideoOut::Y4m(file) = &mut self.video {
+            file.flush()?;
+        }
+        self.audio.finish()?;
+        Ok(self.num_frames)
+    }
+}
+
+/// Converts to Y'CbCr (BT.601), as 3 separate full-resolution planes.
+fn yuv_planes(frame: &Frame) -> Vec<u8> {
+    let n = frame.len();
+    let mut out = vec![0; 3 * n];
+
+    for (i, &pixel) in frame.iter().enumerate() {
+        let [_, r, g, b] = pixel.to_be_bytes().map(|x| x as f64 / 255.);
+
+        let y = 16. + 65.481 * r + 128.553 * g + 24.966 * b;
+        let cb = 128. - 37.797 * r - 74.203 * g + 112.0 * b;
+        let cr = 128. + 112.0 * r - 93.786 * g - 18.214 * b;
+
+        out[i] = y.round() as u8;
+        out[n + i] = cb.round() as u8;
+        out[2 * n + i] = cr.round() as u8;
+    }
+    out
+}
+
+/// 16-bit mono WAV.
+struct Wav {
+    file: BufWriter<File>,
+    /// The cycle of the first sample. (Cycles count from when the emulator
+    /// started, not from when the recording did.)
+    start: u64,
+    num_samples: u64,
+
+    /// The speaker cone's position.
+    high: bool,
+    /// For the high-pass filter.
+    prev_input: f64,
+    prev_output: f64,
+}
+
+impl Wav {
+    fn create(path: &Path, start: u64) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        // We don't know the sizes yet, so they get filled in by `finish`.
+        file.write_all(b"RIFF")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?; // PCM
+        file.write_all(&1u16.to_le_bytes())?; // mono
+        file.write_all(&(SAMPLE_RATE as u32).to_le_bytes())?;
+        file.write_all(&(SAMPLE_RATE as u32 * 2).to_le_bytes())?; // bytes per second
+        file.write_all(&2u16.to_le_bytes())?; // bytes per sample
+        file.write_all(&16u16.to_le_bytes())?; // bits per sample
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self {
+            file,
+            start,
+            num_samples: 0,
+            high: false,
+            prev_input: 0.,
+            prev_output: 0.,
+        })
+    }
+
+    /// Write all the samples before cycle `now`.
+    fn write(&mut self, toggles: &[u64], now: u64) -> Result<()> {
+        let mut toggles = toggles.iter().peekable();
+
+        loop {
+            let t = self.start + self.num_samples * CLOCK_HZ / SAMPLE_RATE;
+            if t >= now {
+                break;
+            }
+            while toggles.next_if(|&&cycle| cycle <= t).is_some() {
+                self.high = !self.high;
+            }
+
+            // The speaker doesn't make any sound just by sitting still in one
+            // position, so filter out the DC offset.
+            let input = if self.high { 1. } else { -1. };
+            let output = input - self.prev_input + 0.995 * self.prev_output;
+            self.prev_input = input;
+            self.prev_output = output;
+
+            let sample = (output * 0.25 * i16::MAX as f64) as i16;
+            self.file.write_all(&sample.to_le_bytes())?;
+            self.num_samples += 1;
+        }
+
+        // Any toggles after the last sample still count.
+        if toggles.count() % 2 == 1 {
+            self.high = !self.high;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        let data_len = self.num_samples as u32 * 2;
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(36 + data_len).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&data_len.to_le_bytes())?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Just the samples.
+    fn load_wav(path: &Path) -> Vec<i16> {
+        let bytes = fs::read(path).unwrap();
+        let data_len = u32::from_le_bytes(bytes[40..44].try_into().unwrap());
+        assert_eq!(data_len as usize, bytes.len() - 44);
+        bytes[44..]
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes(b.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn wav_starting_late() {
+        // 10 seconds after boot, record 100 ms, with a toggle 10 ms in.
+        let start = 10 * CLOCK_HZ;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("late.wav");
+        let mut wav = Wav::create(&path, start).unwrap();
+        wav.write(&[start + CLOCK_HZ / 100], start + CLOCK_HZ / 10)
+            .unwrap();
+        wav.finish().unwrap();
+        let samples = load_wav(&path);
+
+        assert_eq!(samples.len(), SAMPLE_RATE as usize / 10);
+        // The cone moves from low to high, which comes out as a positive
+        // pulse (after the high-pass filter), decaying towards 0.
+        let toggle = samples.iter().position(|&s| s > 0).unwrap();
+        assert_eq!(toggle, SAMPLE_RATE as usize / 100);
+        assert!(samples[..toggle].iter().all(|&s| s < 0));
+        assert!(samples[toggle..].iter().all(|&s| s >= 0));
+        assert_eq!(samples.iter().max(), Some(&samples[toggle]));
+    }
+
+    #[test]
+    fn y4m() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("video.y4m");
+        let mut recorder = Recorder::new(&path, 0).unwrap();
+        let white = vec![0xff_ff_ff; display::W * display::H];
+        let black = vec![0; display::W * display::H];
+        recorder.frame(&white, &[], CYCLES_PER_FRAME).unwrap();
+        recorder.frame(&black, &[], 2 * CYCLES_PER_FRAME).unwrap();
+        assert_eq!(recorder.finish().unwrap(), 2);
+
+        let bytes = fs::read(&path).unwrap();
+        let samples = load_wav(&path.with_extension("wav"));
+
+        let header = format!("YUV4MPEG2 W560 H192 F{CLOCK_HZ}:{CYCLES_PER_FRAME} Ip A16:35 C444\n");
+        assert!(bytes.starts_with(header.as_bytes()));
+        let n = display::W * display::H;
+        let frames = &bytes[header.len()..];
+        assert_eq!(frames.len(), 2 * (6 + 3 * n));
+
+        for (frame, [y, cb, cr]) in frames
+            .chunks(6 + 3 * n)
+            .zip([[235, 128, 128], [16, 128, 128]])
+        {
+            assert_eq!(&frame[..6], b"FRAME\n");
+            let planes = &frame[6..];
+            assert!(planes[..n].iter().all(|&b| b == y));
+            assert!(planes[n..2 * n].iter().all(|&b| b == cb));
+            assert!(planes[2 * n..].iter().all(|&b| b == cr));
+        }
+
+        // Silence, but the right amount of it.
+        let frames_len = 2 * CYCLES_PER_FRAME * SAMPLE_RATE / CLOCK_HZ;
+        assert!(samples.len().abs_diff(frames_len as usize) <= 1);
+    }
+}
@@ -0,0 +1,250 @@
+//! Going backwards in time.
+//!
+//! Every so often, we take a snapshot of the machine, and we log all the input
+//! (keys, etc.) along the way. To go back to any point in time, we restore the
+//! most recent snapshot before that point, then run forward again, feeding in
+//! the same input at the same moments.
+
+use std::{collections::VecDeque, mem};
+
+use anyhow::{ensure, Context, Result};
+
+use crate::{cpu::CLOCK_HZ, Emulator};
+
+/// How often to take a snapshot.
+const INTERVAL: u64 = CLOCK_HZ / 4;
+/// How many snapshots to keep. (That's about 30 seconds' worth, in ~14 MB.)
+const CAPACITY: usize = 120;
+
+/// Anything from outside the machine that affects what it does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    KeyDown(u8),
+    KeyUp(u8),
+    AllKeysUp,
+    AppleKeys { open_apple: bool, solid_apple: bool },
+    Type(String),
+    Reset,
+}
+
+struct Snapshot {
+    cycles: u64,
+    num_instructions_executed: u64,
+    state: Vec<u8>,
+}
+
+pub struct Rewind {
+    /// Oldest first.
+    snapshots: VecDeque<Snapshot>,
+    /// Every input since the oldest snapshot, and when it happened (in
+    /// cycles). Inputs always happen in between instructions.
+    inputs: Vec<(u64, Input)>,
+}
+
+impl Rewind {
+    pub fn new() -> Self {
+        Self {
+            snapshots: VecDeque::new(),
+            inputs: vec![],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.inputs.clear();
+    }
+
+    pub fn log(&mut self, cycles: u64, input: Input) {
+        self.inputs.push((cycles, input));
+    }
+
+    fn needs_snapshot(&self, cycles: u64) -> bool {
+        match self.snapshots.back() {
+            Some(s) => cycles >= s.cycles + INTERVAL,
+            None => true,
+        }
+    }
+
+    fn push(&mut self, snapshot: Snapshot) {
+        if self.snapshots.len() == CAPACITY {
+            self.snapshots.pop_front();
+            let oldest = self.snapshots[0].cycles;
+            self.inputs.retain(|&(cycles, _)| cycles >= oldest);
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    fn truncate(&mut self, cycles: u64) {
+        while self.snapshots.back().is_some_and(|s| s.cycles > cycles) {
+            self.snapshots.pop_back();
+        }
+        self.inputs.retain(|&(c, _)| c <= cycles);
+    }
+}
+
+impl Emulator {
+    /// Called after every instruction.
+    pub(crate) fn maybe_take_snapshot(&mut self) {
+        if self.rewind.needs_snapshot(self.cycles) {
+            let snapshot = Snapshot {
+                cycles: self.cycles,
+                num_instructions_executed: self.num_instructions_executed,
+                state: self.snapshot(),
+            };
+            self.rewind.push(snapshot);
+        }
+    }
+
+    /// Go back one instruction.
+    pub fn reverse_step(&mut self) -> Result<()> {
+        let target = self
+            .num_instructions_executed
+            .checked_sub(1)
+            .context("already at the beginning")?;
+        self.go_back_to(target)
+    }
+
+    /// Go back to the last time we were about to run an instruction with a
+    /// breakpoint on it. Returns false if there wasn't one, in which case we
+    /// go back as far as we can.
+    pub fn reverse_continue(&mut self) -> Result<bool> {
+        ensure!(!self.rewind.snapshots.is_empty(), "nothing to go back to");
+        let mut end = self.num_instructions_executed;
+
+        // Check each stretch between snapshots, newest first.
+        for i in (0..self.rewind.snapshots.len()).rev() {
+            if self.rewind.snapshots[i].num_instructions_executed >= end {
+                continue;
+            }
+
+            self.load_snapshot(i)?;
+            let mut last_hit = None;
+            self.replay(end, |emu| {
+                let pc = emu.cpu.pc();
+                // (Ignoring the ignore counts, since we're going backwards.)
+                if (emu.breakpoints.iter()).any(|bp| bp.addr == pc && bp.condition_holds(emu)) {
+                    last_hit = Some(emu.num_instructions_executed);
+                }
+            });
+
+            if let Some(hit) = last_hit {
+                self.go_back_to(hit)?;
+                return Ok(true);
+            }
+            end = self.rewind.snapshots[i].num_instructions_executed;
+        }
+
+        self.load_snapshot(0)?;
+        self.forget_future();
+        Ok(false)
+    }
+
+    /// Jump back (roughly) this far, e.g. for a rewind button.
+    pub fn rewind_seconds(&mut self, seconds: f64) -> Result<()> {
+        ensure!(!self.rewind.snapshots.is_empty(), "nothing to go back to");
+        let target = self
+            .cycles
+            .saturating_sub((seconds * CLOCK_HZ as f64) as u64);
+        let i = self
+            .rewind
+            .snapshots
+            .iter()
+            .rposition(|s| s.cycles <= target)
+            .unwrap_or(0);
+        self.load_snapshot(i)?;
+        self.forget_future();
+        Ok(())
+    }
+
+    /// Restore the most recent snapshot at (or before) the target, then run
+    /// forward to it.
+    fn go_back_to(&mut self, num_instructions_executed: u64) -> Result<()> {
+        let i = self
+            .rewind
+            .snapshots
+            .iter()
+            .rposition(|s| s.num_instructions_executed <= num_instructions_executed)
+            .context("can't go back that far")?;
+
+        self.load_snapshot(i)?;
+        self.replay(num_instructions_executed, |_| ());
+        self.forget_future();
+        Ok(())
+    }
+
+    fn load_snapshot(&mut self, i: usize) -> Result<()> {
+        // The snapshot's breakpoints might be out of date.
+        let breakpoints = mem::take(&mut self.breakpoints);
+        let state = mem::take(&mut self.rewind.snapshots[i].state);
+
+        let result = self.restore_machine(&state);
+        self.rewind.snapshots[i].state = state;
+        self.breakpoints = breakpoints;
+        result
+    }
+
+    /// Forget about what happened after now, since we're about to change
+    /// it.
+    fn forget_future(&mut self) {
+        self.rewind.truncate(self.cycles);
+        if let Some(recorder) = &mut self.input_recorder {
+            recorder.truncate(self.cycles);
+        }
+        if let Some(player) = &mut self.input_player {
+            player.seek(self.cycles);
+        }
+    }
+
+    /// Run until we've executed this many instructions (in total), feeding in
+    /// the logged inputs as we go. Calls `before_instr` before each one.
+    fn replay(&mut self, num_instructions_executed: u64, mut before_instr: impl FnMut(&Self)) {
+        // Don't record (or trace) the same frames twice, or trigger the same
+        // watchpoints.
+        let recorder = self.recorder.take();
+        let tracer = self.tracer.take();
+        let watchpoints = self.mem.take_watchpoints();
+
+        let inputs = &self.rewind.inputs;
+        let mut next_input = inputs.partition_point(|&(cycles, _)| cycles < self.cycles);
+
+        loop {
+            while let Some((cycles, input)) = self.rewind.inputs.get(next_input) {
+                if *cycles > self.cycles {
+                    break;
+                }
+                let input = input.clone();
+                self.apply_input(&input);
+                next_input += 1;
+            }
+
+            if self.num_instructions_executed >= num_instructions_executed {
+                break;
+            }
+            before_instr(self);
+            self.execute_instr();
+        }
+
+        self.recorder = recorder;
+        self.tracer = tracer;
+        self.mem.set_watchpoints(watchpoints);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_to_go_back_to() {
+        // JMP $0300
+        let mut emu = Emulator::new(&[0x4c, 0x00, 0x03], 0x300, 0x300, vec![]);
+        emu.sim_1000_instrs();
+
+        // Loading a state forgets all the snapshots.
+        let state = emu.snapshot();
+        emu.restore(&state).unwrap();
+        assert!(emu.reverse_step().is_err());
+        assert!(emu.reverse_continue().is_err());
+        assert!(emu.rewind_seconds(1.).is_err());
+    }
+}
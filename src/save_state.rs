@@ -0,0 +1,148 @@
+//! Save states: a snapshot of the whole machine.
+//!
+//! The format starts with a magic number and a version number, followed by a
+//! list of sections. Each section is a 4-byte tag, a length, and then that
+//! many bytes of data. When loading, we skip any sections we don't recognize,
+//! so adding a new section doesn't need a new version. (Only bump the version
+//! if an existing section changes.) All numbers are little-endian.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, ensure, Context, Result};
+
+const MAGIC: &[u8; 8] = b"A2ESTATE";
+const VERSION: u32 = 1;
+
+pub type Tag = [u8; 4];
+
+/// Where numbered save slots go: `state-N.a2s`, in the current directory.
+pub fn slot_path(slot: u32) -> PathBuf {
+    PathBuf::from(format!("state-{slot}.a2s"))
+}
+
+#[derive(Default)]
+pub struct Sections {
+    sections: Vec<(Tag, Vec<u8>)>,
+}
+
+impl Sections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, tag: &Tag, data: Vec<u8>) {
+        self.sections.push((*tag, data));
+    }
+
+    /// The contents of a section. It's an error if it's missing.
+    pub fn get(&self, tag: &Tag) -> Result<Reader<'_>> {
+        self.find(tag)
+            .with_context(|| format!("missing section: {:?}", String::from_utf8_lossy(tag)))
+    }
+
+    /// For sections that older files might not have.
+    pub fn find(&self, tag: &Tag) -> Option<Reader<'_>> {
+        let (_, data) = self.sections.iter().find(|(t, _)| t == tag)?;
+        Some(Reader { tag: *tag, data })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        out.extend(MAGIC);
+        out.extend(VERSION.to_le_bytes());
+        for (tag, data) in &self.sections {
+            out.extend(tag);
+            out.extend((data.len() as u32).to_le_bytes());
+            out.extend(data);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut r = Reader {
+            tag: *b"file",
+            data: bytes,
+        };
+        ensure!(r.bytes(MAGIC.len())? == MAGIC, "not a save state file");
+        let version = r.u32()?;
+        if version > VERSION {
+            bail!("save state is from a newer version of the emulator (version {version})");
+        }
+
+        let mut sections = Self::new();
+        while !r.data.is_empty() {
+            let tag = r.bytes(4)?.try_into().unwrap();
+            let len = r.u32()? as usize;
+            let data = r.bytes(len)?;
+            sections.add(&tag, data.to_vec());
+        }
+        Ok(sections)
+    }
+}
+
+/// Reads the fields of a section, in order.
+pub struct Reader<'a> {
+    tag: Tag,
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.data.len() < n {
+            bail!(
+                "section {:?} is too short",
+                String::from_utf8_lossy(&self.tag)
+            );
+        }
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_sections_are_skipped() {
+        let mut sections = Sections::new();
+        sections.add(b"NEW!", vec![1, 2, 3]);
+        sections.add(b"TEST", vec![0x34, 0x12]);
+
+        let sections = Sections::from_bytes(&sections.to_bytes()).unwrap();
+        assert_eq!(sections.get(b"TEST").unwrap().u16().unwrap(), 0x1234);
+        assert!(sections.get(b"GONE").is_err());
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let mut bytes = Sections::new().to_bytes();
+        bytes[MAGIC.len()] = VERSION as u8 + 1;
+        assert!(Sections::from_bytes(&bytes).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! Saving the screen to an image file.
+
+use std::{fs::File, io::BufWriter, path::Path};
+
+use anyhow::{ensure, Result};
+
+use crate::display::{self, hgr, Frame};
+
+/// Write the frame to a PNG file.
+///
+/// With `scale` 1, the image is at the screen's native resolution: 280x192,
+/// or 560x192 if there's any 80-column text, double hi-res, or color fringing
+/// on screen. Otherwise, the image is `scale` times the size of the hi-res
+/// screen (like the GUI window, which is 3x).
+pub fn save_png(frame: &Frame, path: impl AsRef<Path>, scale: usize) -> Result<()> {
+    ensure!(scale >= 1, "scale must be at least 1");
+    assert_eq!(frame.len(), display::W * display::H);
+
+    let (width, height) = if scale == 1 && is_doubled(frame) {
+        (hgr::W, hgr::H)
+    } else if scale == 1 {
+        (display::W, display::H)
+    } else {
+        (hgr::W * scale, hgr::H * scale)
+    };
+
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = frame[y * display::H / height * display::W + x * display::W / width];
+            let [_, r, g, b] = pixel.to_be_bytes();
+            rgb.extend([r, g, b]);
+        }
+    }
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&rgb)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Is every dot exactly twice as wide as it needs to be?
+fn is_doubled(frame: &Frame) -> bool {
+    frame.chunks_exact(2).all(|pair| pair[0] == pair[1])
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    /// Read a PNG back in, as RGB pixels.
+    fn load_png(path: &Path) -> (u32, u32, Vec<[u8; 3]>) {
+        let decoder = png::Decoder::new(File::open(path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        let pixels = buf[..info.buffer_size()]
+            .chunks_exact(3)
+            .map(|rgb| rgb.try_into().unwrap())
+            .collect();
+        (info.width, info.height, pixels)
+    }
+
+    #[test_case(false, 1, 560, 192; "native")]
+    #[test_case(true, 1, 280, 192; "native, doubled")]
+    #[test_case(true, 3, 840, 576; "scaled")]
+    fn round_trip(doubled: bool, scale: usize, width: u32, height: u32) {
+        // A different color for each pixel (or pair of dots, if doubled).
+        let color = |x: usize, y: usize| (x as u32) << 8 | y as u32 | 0xff00_0000;
+        let frame: Frame = (0..display::H)
+            .flat_map(|y| (0..display::W).map(move |x| (x, y)))
+            .map(|(x, y)| color(if doubled { x / 2 } else { x }, y))
+            .collect();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("screenshot.png");
+        save_png(&frame, &path, scale).unwrap();
+        let (w, h, pixels) = load_png(&path);
+
+        assert_eq!((w, h), (width, height));
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                // Back to the frame's coordinates.
+                let fx = x * display::W / width as usize;
+                let fy = y * display::H / height as usize;
+                let [_, r, g, b] = frame[fy * display::W + fx].to_be_bytes();
+                assert_eq!(pixels[y * width as usize + x], [r, g, b], "({x}, {y})");
+            }
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! The built-in speaker.
+//!
+//! Software makes sound by accessing $c030, which toggles the speaker cone
+//! between two positions. Whatever rate the program toggles it at is the
+//! pitch you hear. So all we need to remember is *when* each toggle happened.
+
+use anyhow::Result;
+
+use crate::save_state::Sections;
+
+#[derive(Clone)]
+pub struct Speaker {
+    /// Cycles since the emulator started.
+    cycle: u64,
+    /// When the speaker was toggled, since the last call to `take_toggles`.
+    toggles: Vec<u64>,
+}
+
+impl Speaker {
+    pub fn new() -> Self {
+        Self {
+            cycle: 0,
+            toggles: vec![],
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u8) {
+        self.cycle += cycles as u64;
+    }
+
+    pub fn toggle(&mut self) {
+        self.toggles.push(self.cycle);
+    }
+
+    pub fn save_state(&self, sections: &mut Sections) {
+        sections.add(b"SPKR", self.cycle.to_le_bytes().to_vec());
+    }
+
+    pub fn load_state(&mut self, sections: &Sections) -> Result<()> {
+        self.cycle = sections.get(b"SPKR")?.u64()?;
+        self.toggles.clear();
+        Ok(())
+    }
+
+    /// The cycle numbers of each toggle, oldest first.
+    pub fn take_toggles(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.toggles)
+    }
+}
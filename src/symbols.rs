@@ -0,0 +1,216 @@
+//! Names for addresses. (For the debugger, and the disassembler.)
+//!
+//! Out of the box, there are names for the ROM routines and soft switches
+//! (see `builtin`). Then more can come from:
+//! * the ELF file that llvm-mos makes (including DWARF line info, if it was
+//!   built with `-g`)
+//! * a ca65/ld65 debug file (`ld65 --dbgfile`)
+//! * a VICE-style label file, with lines like `al C:0803 .main`
+
+mod builtin;
+mod ca65;
+mod elf;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    path::Path,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use itertools::Itertools;
+
+use crate::hex;
+
+/// How far past a symbol (or source line) an address can be, and still
+/// count as part of it. (We don't know how big anything is.)
+const NEARBY: u16 = 0x100;
+
+#[derive(Default, Clone)]
+pub struct SymbolTable {
+    names: BTreeMap<u16, String>,
+    addrs: HashMap<String, u16>,
+    lines: BTreeMap<u16, SourceLine>,
+}
+
+/// Where an instruction came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: u32,
+}
+
+impl fmt::Display for SourceLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// With the well-known addresses in the ROM, etc. already filled in.
+    pub fn builtin() -> Self {
+        let mut symbols = Self::new();
+        for &(addr, name) in builtin::SYMBOLS {
+            symbols.insert(addr, name);
+        }
+        symbols
+    }
+
+    /// Load symbols from a file, in any of the formats above. (We go by
+    /// what's in it, not the file extension.) Returns how many symbols there
+    /// were, including any that replaced an existing name.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<usize> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).with_context(|| format!("couldn't read {}", path.display()))?;
+
+        if bytes.starts_with(b"\x7fELF") {
+            elf::load(self, &bytes)
+        } else {
+            let text = String::from_utf8_lossy(&bytes);
+            if text.starts_with("version\t") {
+                ca65::load(self, &text)
+            } else {
+                load_vice(self, &text)
+            }
+        }
+        .with_context(|| format!("couldn't load symbols from {}", path.display()))
+    }
+
+    /// If there's already a name for `addr`, the new one wins. (But the old
+    /// name still works for looking things up.)
+    pub fn insert(&mut self, addr: u16, name: impl Into<String>) {
+        let name = name.into();
+        self.addrs.insert(name.clone(), addr);
+        self.names.insert(addr, name);
+    }
+
+    /// Unlike names, the first one wins. (A line table often has a few
+    /// entries for the same address, and the first is usually the one you
+    /// want.)
+    pub fn insert_line(&mut self, addr: u16, line: SourceLine) {
+        self.lines.entry(addr).or_insert(line);
+    }
+
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        self.names.get(&addr).map(|name| name.as_str())
+    }
+
+    pub fn addr(&self, name: &str) -> Option<u16> {
+        self.addrs.get(name).copied()
+    }
+
+    /// The source line for the instruction at exactly `addr`.
+    pub fn line(&self, addr: u16) -> Option<&SourceLine> {
+        self.lines.get(&addr)
+    }
+
+    /// E.g. `main+$12`, for an address somewhere in `main`. (Or at least,
+    /// not far after it.)
+    pub fn describe(&self, addr: u16) -> Option<String> {
+        let (&start, name) = self.names.range(..=addr).next_back()?;
+        match addr - start {
+            0 => Some(name.clone()),
+            offset if offset < NEARBY => Some(format!("{name}+${offset:x}")),
+            _ => None,
+        }
+    }
+
+    /// The source line that `addr` is part of. (Unlike `line`, it doesn't
+    /// have to be the start of one.)
+    pub fn line_containing(&self, addr: u16) -> Option<&SourceLine> {
+        let (&start, line) = self.lines.range(..=addr).next_back()?;
+        (addr - start < NEARBY).then_some(line)
+    }
+
+    /// A symbol name, or else an address (in hex). Names win, since e.g.
+    /// `add` is also valid hex.
+    pub fn resolve(&self, s: &str) -> Result<u16> {
+        if let Some(addr) = self.addr(s) {
+            return Ok(addr);
+        }
+        hex::decode_u16(s).with_context(|| format!("no symbol or address {s:?}"))
+    }
+}
+
+/// E.g. `al C:0803 .main`. (The `C:` is optional, and so is the dot.)
+/// Returns how many symbols there were.
+fn load_vice(symbols: &mut SymbolTable, text: &str) -> Result<usize> {
+    let mut n = 0;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        let words = line.split_whitespace().collect_vec();
+        let [_, addr, name] = words[..] else {
+            bail!("line {}: expected `al <addr> .<label>`", i + 1);
+        };
+        ensure!(words[0] == "al", "line {}: expected `al`", i + 1);
+
+        let addr = addr.strip_prefix("C:").unwrap_or(addr);
+        let addr = hex::decode_u16(addr).with_context(|| format!("line {}", i + 1))?;
+        symbols.insert(addr, name.strip_prefix('.').unwrap_or(name));
+        n += 1;
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vice() {
+        let mut symbols = SymbolTable::new();
+        let n = load_vice(
+            &mut symbols,
+            "al C:0803 .main\nal 0810 .loop\n\n# comment\n",
+        )
+        .unwrap();
+        assert_eq!(n, 2);
+        assert_eq!(symbols.addr("main"), Some(0x803));
+        assert_eq!(symbols.name(0x810), Some("loop"));
+        assert!(load_vice(&mut symbols, "break 0803").is_err());
+    }
+
+    #[test]
+    fn renaming_counts() {
+        let mut symbols = SymbolTable::builtin();
+        let n = load_vice(&mut symbols, "al fded .print_char").unwrap();
+        assert_eq!(n, 1);
+        assert_eq!(symbols.name(0xfded), Some("print_char"));
+        assert_eq!(symbols.names.len(), builtin::SYMBOLS.len());
+    }
+
+    #[test]
+    fn builtin_names_are_unique() {
+        let symbols = SymbolTable::builtin();
+        assert_eq!(symbols.names.len(), builtin::SYMBOLS.len());
+        assert_eq!(symbols.addrs.len(), builtin::SYMBOLS.len());
+        assert_eq!(symbols.addr("COUT"), Some(0xfded));
+    }
+
+    #[test]
+    fn describe() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x800, "main");
+        assert_eq!(symbols.describe(0x800).as_deref(), Some("main"));
+        assert_eq!(symbols.describe(0x812).as_deref(), Some("main+$12"));
+        assert_eq!(symbols.describe(0x7ff), None);
+        assert_eq!(symbols.describe(0x2000), None);
+    }
+
+    #[test]
+    fn resolve() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x1234, "add");
+        assert_eq!(symbols.resolve("add").unwrap(), 0x1234);
+        assert_eq!(symbols.resolve("fded").unwrap(), 0xfded);
+        assert!(symbols.resolve("main").is_err());
+    }
+}
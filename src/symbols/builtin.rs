@@ -0,0 +1,235 @@
+//! Well-known addresses in the Apple IIe: the monitor ROM, Applesoft, the
+//! 80-column firmware, and the soft switches. Mostly from the IIe Technical
+//! Reference Manual, and the commented disassemblies linked in `rom/README`.
+//!
+//! Some soft switches do different things for reads and writes (e.g. $c000
+//! is the keyboard when you read it, and 80STOREOFF when you write it), but
+//! there's only room for one name. Those get whichever one programs use more.
+
+pub const SYMBOLS: &[(u16, &str)] = &[
+    // Zero page, used by the monitor.
+    (0x0020, "WNDLFT"),
+    (0x0021, "WNDWDTH"),
+    (0x0022, "WNDTOP"),
+    (0x0023, "WNDBTM"),
+    (0x0024, "CH"),
+    (0x0025, "CV"),
+    (0x0026, "GBASL"),
+    (0x0027, "GBASH"),
+    (0x0028, "BASL"),
+    (0x0029, "BASH"),
+    (0x002a, "BAS2L"),
+    (0x002b, "BAS2H"),
+    (0x0030, "COLOR"),
+    (0x0031, "MODE"),
+    (0x0032, "INVFLG"),
+    (0x0033, "PROMPT"),
+    (0x0036, "CSWL"),
+    (0x0037, "CSWH"),
+    (0x0038, "KSWL"),
+    (0x0039, "KSWH"),
+    (0x003c, "A1L"),
+    (0x003d, "A1H"),
+    (0x003e, "A2L"),
+    (0x003f, "A2H"),
+    (0x0042, "A4L"),
+    (0x0043, "A4H"),
+    (0x004e, "RNDL"),
+    (0x004f, "RNDH"),
+    // Page 3 vectors.
+    (0x03f0, "BRKV"),
+    (0x03f2, "SOFTEV"),
+    (0x03f4, "PWREDUP"),
+    (0x03f5, "AMPERV"),
+    (0x03f8, "USRADR"),
+    (0x03fb, "NMI"),
+    (0x03fe, "IRQLOC"),
+    // Soft switches.
+    (0xc000, "KBD"),
+    (0xc001, "80STOREON"),
+    (0xc002, "RDMAINRAM"),
+    (0xc003, "RDCARDRAM"),
+    (0xc004, "WRMAINRAM"),
+    (0xc005, "WRCARDRAM"),
+    (0xc008, "SETSTDZP"),
+    (0xc009, "SETALTZP"),
+    (0xc00c, "CLR80VID"),
+    (0xc00d, "SET80VID"),
+    (0xc00e, "CLRALTCHAR"),
+    (0xc00f, "SETALTCHAR"),
+    (0xc010, "KBDSTRB"),
+    (0xc011, "RDLCBNK2"),
+    (0xc012, "RDLCRAM"),
+    (0xc013, "RDRAMRD"),
+    (0xc014, "RDRAMWRT"),
+    (0xc016, "RDALTZP"),
+    (0xc018, "RD80STORE"),
+    (0xc019, "RDVBLBAR"),
+    (0xc01a, "RDTEXT"),
+    (0xc01b, "RDMIXED"),
+    (0xc01c, "RDPAGE2"),
+    (0xc01d, "RDHIRES"),
+    (0xc01e, "RDALTCHAR"),
+    (0xc01f, "RD80VID"),
+    (0xc020, "TAPEOUT"),
+    (0xc030, "SPKR"),
+    (0xc050, "TXTCLR"),
+    (0xc051, "TXTSET"),
+    (0xc052, "MIXCLR"),
+    (0xc053, "MIXSET"),
+    (0xc054, "TXTPAGE1"),
+    (0xc055, "TXTPAGE2"),
+    (0xc056, "LORES"),
+    (0xc057, "HIRES"),
+    (0xc058, "CLRAN0"),
+    (0xc059, "SETAN0"),
+    (0xc05a, "CLRAN1"),
+    (0xc05b, "SETAN1"),
+    (0xc05c, "CLRAN2"),
+    (0xc05d, "SETAN2"),
+    (0xc05e, "DHIRESON"),
+    (0xc05f, "DHIRESOFF"),
+    (0xc060, "TAPEIN"),
+    (0xc061, "BUTN0"),
+    (0xc062, "BUTN1"),
+    (0xc063, "BUTN2"),
+    (0xc064, "PADDL0"),
+    (0xc065, "PADDL1"),
+    (0xc070, "PTRIG"),
+    (0xc07e, "IOUDISON"),
+    (0xc07f, "IOUDISOFF"),
+    // The language card. (These don't have standard names, so: what you
+    // read, what you can write (if anything), and which bank.)
+    (0xc080, "LCRAM2"),
+    (0xc081, "LCROMWR2"),
+    (0xc082, "LCROM2"),
+    (0xc083, "LCRAMWR2"),
+    (0xc088, "LCRAM1"),
+    (0xc089, "LCROMWR1"),
+    (0xc08a, "LCROM1"),
+    (0xc08b, "LCRAMWR1"),
+    // The 80-column firmware.
+    (0xc300, "BASICINT"),
+    (0xc305, "BASICIN"),
+    (0xc307, "BASICOUT"),
+    (0xcfff, "CLRROM"),
+    // Applesoft.
+    (0xd412, "ERROR"),
+    (0xd43c, "RESTART"),
+    (0xd52c, "INLIN"),
+    (0xd7d2, "NEWSTT"),
+    (0xdafb, "CRDO"),
+    (0xdb3a, "STROUT"),
+    (0xdb5c, "OUTDO"),
+    (0xdd67, "FRMNUM"),
+    (0xdd7b, "FRMEVL"),
+    (0xdeb8, "CHKCLS"),
+    (0xdebb, "CHKOPN"),
+    (0xdebe, "CHKCOM"),
+    (0xdec0, "SYNCHR"),
+    (0xdec9, "SYNERR"),
+    (0xe10c, "AYINT"),
+    (0xe2f2, "GIVAYF"),
+    (0xe301, "SNGFLT"),
+    (0xe6f8, "GETBYT"),
+    (0xe752, "GETADR"),
+    (0xe7a7, "FSUB"),
+    (0xe7be, "FADD"),
+    (0xe97f, "FMULT"),
+    (0xea66, "FDIV"),
+    (0xed24, "LINPRT"),
+    (0xed34, "FOUT"),
+    (0xefae, "RND"),
+    (0xf3d8, "HGR2"),
+    (0xf3e2, "HGR"),
+    (0xf3f2, "HCLR"),
+    (0xf3f6, "BKGND"),
+    (0xf411, "HPOSN"),
+    (0xf457, "HPLOT0"),
+    (0xf53a, "HGLIN"),
+    (0xf5cb, "HFIND"),
+    (0xf601, "DRAW0"),
+    (0xf65d, "XDRAW0"),
+    // The monitor.
+    (0xf800, "PLOT"),
+    (0xf819, "HLINE"),
+    (0xf828, "VLINE"),
+    (0xf832, "CLRSCR"),
+    (0xf836, "CLRTOP"),
+    (0xf847, "GBASCALC"),
+    (0xf85f, "NXTCOL"),
+    (0xf864, "SETCOL"),
+    (0xf871, "SCRN"),
+    (0xf88c, "INSDS1"),
+    (0xf8d0, "INSTDSP"),
+    (0xf940, "PRNTYX"),
+    (0xf941, "PRNTAX"),
+    (0xf944, "PRNTX"),
+    (0xf948, "PRBLNK"),
+    (0xf94a, "PRBL2"),
+    (0xf953, "PCADJ"),
+    (0xfa4c, "BREAK"),
+    (0xfa59, "OLDBRK"),
+    (0xfa62, "RESET"),
+    (0xfaa6, "PWRUP"),
+    (0xfad7, "REGDSP"),
+    (0xfb1e, "PREAD"),
+    (0xfb2f, "INIT"),
+    (0xfb39, "SETTXT"),
+    (0xfb40, "SETGR"),
+    (0xfb4b, "SETWND"),
+    (0xfb5b, "TABV"),
+    (0xfb6f, "SETPWRC"),
+    (0xfbc1, "BASCALC"),
+    (0xfbdd, "BELL1"),
+    (0xfbf4, "ADVANCE"),
+    (0xfbfd, "VIDOUT"),
+    (0xfc10, "BS"),
+    (0xfc1a, "UP"),
+    (0xfc22, "VTAB"),
+    (0xfc24, "VTABZ"),
+    (0xfc42, "CLREOP"),
+    (0xfc58, "HOME"),
+    (0xfc62, "CR"),
+    (0xfc66, "LF"),
+    (0xfc70, "SCROLL"),
+    (0xfc9c, "CLREOL"),
+    (0xfc9e, "CLEOLZ"),
+    (0xfca8, "WAIT"),
+    (0xfcb4, "NXTA4"),
+    (0xfcba, "NXTA1"),
+    (0xfd0c, "RDKEY"),
+    (0xfd1b, "KEYIN"),
+    (0xfd35, "RDCHAR"),
+    (0xfd67, "GETLNZ"),
+    (0xfd6a, "GETLN"),
+    (0xfd6f, "GETLN1"),
+    (0xfd8b, "CROUT1"),
+    (0xfd8e, "CROUT"),
+    (0xfd92, "PRA1"),
+    (0xfdda, "PRBYTE"),
+    (0xfde3, "PRHEX"),
+    (0xfde5, "PRHEXZ"),
+    (0xfded, "COUT"),
+    (0xfdf0, "COUT1"),
+    (0xfdf6, "COUTZ"),
+    (0xfe2c, "MOVE"),
+    (0xfe36, "VFY"),
+    (0xfe5e, "LIST"),
+    (0xfe80, "SETINV"),
+    (0xfe84, "SETNORM"),
+    (0xfe89, "SETKBD"),
+    (0xfe8b, "INPORT"),
+    (0xfe93, "SETVID"),
+    (0xfe95, "OUTPORT"),
+    (0xfeb6, "GO"),
+    (0xff2d, "PRERR"),
+    (0xff3a, "BELL"),
+    (0xff3f, "RESTORE"),
+    (0xff4a, "SAVE"),
+    (0xff59, "OLDRST"),
+    (0xff65, "MON"),
+    (0xff69, "MONZ"),
+    (0xffa7, "GETNUM"),
+    (0xffc7, "ZMODE"),
+];
@@ -0,0 +1,153 @@
+//! ca65/ld65 debug files, from `ld65 --dbgfile`.
+//!
+//! Each line is a record (the first gap is a tab), like:
+//!
+//! ```text
+//! seg    id=0,name="CODE",start=0x000803,size=0x0010,addrsize=absolute,type=ro
+//! sym    id=0,name="main",addrsize=absolute,scope=0,def=1,val=0x803,seg=0,type=lab
+//! ```
+//!
+//! Source lines are indirect: a line has spans, a span is an offset into a
+//! segment, and a segment has a start address.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+
+use super::{SourceLine, SymbolTable};
+
+/// Returns how many symbols there were.
+pub fn load(symbols: &mut SymbolTable, text: &str) -> Result<usize> {
+    let mut num_symbols = 0;
+    let mut files = HashMap::new();
+    let mut segs = HashMap::new();
+    let mut spans = HashMap::new();
+    let mut lines = vec![];
+
+    for (i, line) in text.lines().enumerate() {
+        let Some((kind, fields)) = line.split_once('\t') else {
+            continue;
+        };
+        let record = parse_record(fields).with_context(|| format!("line {}", i + 1))?;
+        let get = |key: &str| {
+            record
+                .get(key)
+                .copied()
+                .with_context(|| format!("line {}: {kind} without {key}", i + 1))
+        };
+
+        match kind {
+            "file" => {
+                files.insert(num(get("id")?)?, get("name")?.to_string());
+            }
+            "seg" => {
+                segs.insert(num(get("id")?)?, num(get("start")?)?);
+            }
+            "span" => {
+                let seg = num(get("seg")?)?;
+                spans.insert(num(get("id")?)?, (seg, num(get("start")?)?));
+            }
+            // Type 0 is plain assembly. (The others are macros, and lines
+            // from the C compiler, which we skip for now.)
+            "line" if record.get("type").is_none_or(|&t| t == "0") => {
+                if let Some(span) = record.get("span") {
+                    lines.push((num(get("file")?)?, num(get("line")?)?, span.to_string()));
+                }
+            }
+            "sym" if record.get("type") == Some(&"lab") => {
+                let addr = num(get("val")?)?;
+                if let Ok(addr) = u16::try_from(addr) {
+                    symbols.insert(addr, get("name")?);
+                    num_symbols += 1;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    for (file, line, line_spans) in lines {
+        let file = files.get(&file).context("line in an unknown file")?;
+        // E.g. `span=3+4`.
+        for span in line_spans.split('+') {
+            let (seg, offset) = spans.get(&num(span)?).context("unknown span")?;
+            let start = segs.get(seg).context("unknown segment")?;
+            if let Ok(addr) = u16::try_from(start + offset) {
+                symbols.insert_line(
+                    addr,
+                    SourceLine {
+                        file: file.clone(),
+                        line,
+                    },
+                );
+            }
+        }
+    }
+
+    Ok(num_symbols)
+}
+
+/// `key=value,key="value, with commas"`
+fn parse_record(s: &str) -> Result<HashMap<&str, &str>> {
+    let mut record = HashMap::new();
+    let mut rest = s;
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once('=').context("expected key=value")?;
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').context("unterminated string")?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => after.split_at(after.find(',').unwrap_or(after.len())),
+        };
+        record.insert(key, value);
+        if !after.is_empty() && !after.starts_with(',') {
+            bail!("expected a comma after {key}");
+        }
+        rest = after.strip_prefix(',').unwrap_or(after);
+    }
+    Ok(record)
+}
+
+fn num(s: &str) -> Result<u32> {
+    let n = match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    n.with_context(|| format!("invalid number: {s:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbols_and_lines() {
+        let text = "\
+version	major=2,minor=0
+info	csym=0,file=1,lib=0,line=2,mod=1,scope=1,seg=1,span=2,sym=2,type=1
+file	id=0,name=\"hello, world.s\",size=100,mtime=0x5f000000,mod=0
+line	id=0,file=0,line=10,span=0
+line	id=1,file=0,line=12,type=2,span=1
+mod	id=0,name=\"hello.o\",file=0
+seg	id=0,name=\"CODE\",start=0x000803,size=0x0010,addrsize=absolute,type=ro
+span	id=0,seg=0,start=0,size=3
+span	id=1,seg=0,start=3,size=2
+sym	id=0,name=\"main\",addrsize=absolute,scope=0,def=0,val=0x803,seg=0,type=lab
+sym	id=1,name=\"COUT\",addrsize=absolute,scope=0,def=1,val=0xFDED,type=equ
+";
+        let mut symbols = SymbolTable::new();
+        assert_eq!(load(&mut symbols, text).unwrap(), 1);
+
+        assert_eq!(symbols.addr("main"), Some(0x803));
+        assert_eq!(symbols.addr("COUT"), None);
+        assert_eq!(
+            symbols.line(0x803),
+            Some(&SourceLine {
+                file: "hello, world.s".to_string(),
+                line: 10
+            })
+        );
+        // A macro.
+        assert_eq!(symbols.line(0x806), None);
+    }
+}
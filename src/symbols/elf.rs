@@ -0,0 +1,225 @@
+//! The ELF files that llvm-mos makes, alongside the memory image. Symbols
+//! come from the symbol table, and source lines from the DWARF line table
+//! (if there is one).
+
+use anyhow::Result;
+use object::{Object, ObjectSection, ObjectSymbol, SymbolKind, SymbolSection};
+
+use super::{SourceLine, SymbolTable};
+
+/// Returns how many symbols there were.
+pub fn load(symbols: &mut SymbolTable, bytes: &[u8]) -> Result<usize> {
+    let file = object::File::parse(bytes)?;
+    let mut num_symbols = 0;
+
+    for symbol in file.symbols() {
+        // Skip absolute symbols, since they're mostly constants (like
+        // `__STACK_SIZE`), not addresses. (Plain assembly labels have no
+        // type, so `Unknown` is fine, as long as it's in a section.)
+        if !matches!(
+            symbol.kind(),
+            SymbolKind::Text | SymbolKind::Data | SymbolKind::Unknown
+        ) || !matches!(symbol.section(), SymbolSection::Section(_))
+        {
+            continue;
+        }
+        let (Ok(name), Ok(addr)) = (symbol.name(), u16::try_from(symbol.address())) else {
+            continue;
+        };
+        if !name.is_empty() {
+            symbols.insert(addr, name);
+            num_symbols += 1;
+        }
+    }
+
+    load_lines(symbols, &file)?;
+    Ok(num_symbols)
+}
+
+fn load_lines(symbols: &mut SymbolTable, file: &object::File) -> Result<()> {
+    let load_section = |id: gimli::SectionId| -> Result<&[u8]> {
+        Ok(match file.section_by_name(id.name()) {
+            Some(section) => section.data()?,
+            None => &[],
+        })
+    };
+    let sections = gimli::DwarfSections::load(load_section)?;
+    let dwarf = sections.borrow(|section| gimli::EndianSlice::new(section, gimli::LittleEndian));
+
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let Some(program) = unit.line_program.clone() else {
+            continue;
+        };
+
+        let mut rows = program.rows();
+        while let Some((header, row)) = rows.next_row()? {
+            if row.end_sequence() || !row.is_stmt() {
+                continue;
+            }
+            let (Some(line), Some(file)) = (row.line(), row.file(header)) else {
+                continue;
+            };
+            let Ok(addr) = u16::try_from(row.address()) else {
+                continue;
+            };
+
+            let file = dwarf.attr_string(&unit, file.path_name())?;
+            symbols.insert_line(
+                addr,
+                SourceLine {
+                    file: file.to_string_lossy().into_owned(),
+                    line: line.get() as u32,
+                },
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use object::{elf, write::elf::Writer, Endianness};
+
+    use super::*;
+
+    /// llvm-mos's machine number.
+    const EM_MOS: u16 = 6502;
+
+    /// A tiny llvm-mos style executable, with one of each kind of symbol
+    /// that we care about:
+    ///
+    /// ```text
+    ///         .text           ; at $0803
+    /// main:   nop             ; a function
+    /// loop:   jmp loop        ; a plain label, with no type
+    ///         .data           ; at $0806
+    /// counter: .byte 0        ; an object
+    /// STACK_SIZE = $100       ; absolute, i.e. a constant, not an address
+    /// ```
+    ///
+    /// Plus a file symbol, `labels.s`, like the assembler adds.
+    fn labels_elf() -> Vec<u8> {
+        let text = [0xea, 0x4c, 0x04, 0x08];
+        let data = [0];
+
+        let mut bytes = vec![];
+        let mut w = Writer::new(Endianness::Little, false, &mut bytes);
+
+        w.reserve_file_header();
+        w.reserve_null_section_index();
+        let text_name = w.add_section_name(b".text");
+        let text_index = w.reserve_section_index();
+        let data_name = w.add_section_name(b".data");
+        let data_index = w.reserve_section_index();
+        w.reserve_symtab_section_index();
+        w.reserve_strtab_section_index();
+        w.reserve_shstrtab_section_index();
+
+        // (Locals first, as ELF requires.)
+        let symbols = [
+            (b"labels.s".as_slice(), None, elf::STB_LOCAL, elf::STT_FILE, elf::SHN_ABS, 0),
+            (b"loop", Some(text_index), elf::STB_LOCAL, elf::STT_NOTYPE, 0, 0x804),
+            (b"main", Some(text_index), elf::STB_GLOBAL, elf::STT_FUNC, 0, 0x803),
+            (b"counter", Some(data_index), elf::STB_GLOBAL, elf::STT_OBJECT, 0, 0x806),
+            (b"STACK_SIZE", None, elf::STB_GLOBAL, elf::STT_NOTYPE, elf::SHN_ABS, 0x100),
+        ];
+        let num_local = 1 + 2;
+        w.reserve_null_symbol_index();
+        let names: Vec<_> = symbols
+            .iter()
+            .map(|&(name, section, ..)| {
+                w.reserve_symbol_index(section);
+                w.add_string(name)
+            })
+            .collect();
+
+        let text_offset = w.reserve(text.len(), 1);
+        let data_offset = w.reserve(data.len(), 1);
+        w.reserve_symtab();
+        w.reserve_strtab();
+        w.reserve_shstrtab();
+        w.reserve_section_headers();
+
+        w.write_file_header(&object::write::elf::FileHeader {
+            os_abi: elf::ELFOSABI_NONE,
+            abi_version: 0,
+            e_type: elf::ET_EXEC,
+            e_machine: EM_MOS,
+            e_entry: 0x803,
+            e_flags: 0,
+        })
+        .unwrap();
+        w.write(&text);
+        w.write(&data);
+
+        w.write_null_symbol();
+        for (&(_, section, bind, kind, shndx, value), name) in symbols.iter().zip(names) {
+            w.write_symbol(&object::write::elf::Sym {
+                name: Some(name),
+                section,
+                st_info: bind << 4 | kind,
+                st_other: elf::STV_DEFAULT,
+                st_shndx: shndx,
+                st_value: value,
+                st_size: 0,
+            });
+        }
+        w.write_strtab();
+        w.write_shstrtab();
+
+        w.write_null_section_header();
+        for (name, sh_type, sh_flags, addr, offset, size) in [
+            (
+                text_name,
+                elf::SHT_PROGBITS,
+                elf::SHF_ALLOC | elf::SHF_EXECINSTR,
+                0x803,
+                text_offset,
+                text.len(),
+            ),
+            (
+                data_name,
+                elf::SHT_PROGBITS,
+                elf::SHF_ALLOC | elf::SHF_WRITE,
+                0x806,
+                data_offset,
+                data.len(),
+            ),
+        ] {
+            w.write_section_header(&object::write::elf::SectionHeader {
+                name: Some(name),
+                sh_type,
+                sh_flags: sh_flags.into(),
+                sh_addr: addr,
+                sh_offset: offset as u64,
+                sh_size: size as u64,
+                sh_link: 0,
+                sh_info: 0,
+                sh_addralign: 1,
+                sh_entsize: 0,
+            });
+        }
+        w.write_symtab_section_header(num_local);
+        w.write_strtab_section_header();
+        w.write_shstrtab_section_header();
+
+        bytes
+    }
+
+    #[test]
+    fn symbol_kinds() {
+        let bytes = labels_elf();
+        let mut symbols = SymbolTable::new();
+        let n = load(&mut symbols, &bytes).unwrap();
+
+        assert_eq!(symbols.addr("main"), Some(0x803));
+        assert_eq!(symbols.addr("loop"), Some(0x804));
+        assert_eq!(symbols.addr("counter"), Some(0x806));
+        assert_eq!(symbols.addr("STACK_SIZE"), None);
+        assert_eq!(symbols.addr("labels.s"), None);
+        assert_eq!(n, 3);
+    }
+}
@@ -0,0 +1,185 @@
+//! Logging every instruction to a file, to diff against other emulators
+//! when chasing bugs.
+//!
+//! The format is like Nintendulator's (and so the famous `nestest.log`),
+//! minus the PPU columns:
+//!
+//! ```text
+//! FA62  D8        CLD                             A:00 X:00 Y:00 P:04 SP:FC CYC:0
+//! ```
+//!
+//! The disassembly deliberately doesn't use any symbols, so the lines match
+//! what other emulators print. Optionally, the symbol and source line go in a
+//! comment at the end, where they don't get in the way of the columns:
+//!
+//! ```text
+//! 0803  A9 12     LDA #$12                        A:00 X:00 Y:00 P:04 SP:FC CYC:0 ; main (hello.s:3)
+//! ```
+
+use std::{
+    fs::File,
+    io::{prelude::*, BufWriter},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use itertools::Itertools;
+
+use crate::{
+    cpu::{disasm, Cpu, Register},
+    memory::AddressSpace,
+    symbols::SymbolTable,
+};
+
+pub struct Tracer {
+    path: PathBuf,
+    out: BufWriter<File>,
+    /// Only log instructions in here, e.g. to leave out the ROM.
+    pub range: RangeInclusive<u16>,
+    /// While paused (`trace off`), the file stays open.
+    pub on: bool,
+    /// Add the comment with the symbol and source line.
+    pub symbols: bool,
+    num_lines: u64,
+    no_symbols: SymbolTable,
+}
+
+impl Tracer {
+    pub fn new(path: impl AsRef<Path>, range: RangeInclusive<u16>) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::create(path).with_context(|| format!("couldn't create {}", path.display()))?;
+        Ok(Self {
+            path: path.to_owned(),
+            out: BufWriter::new(file),
+            range,
+            on: true,
+            symbols: false,
+            num_lines: 0,
+            no_symbols: SymbolTable::new(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Call this just before the CPU runs the instruction at its PC.
+    pub fn log(
+        &mut self,
+        cpu: &Cpu,
+        mem: &AddressSpace,
+        symbols: &SymbolTable,
+        cycles: u64,
+    ) -> Result<()> {
+        let pc = cpu.pc();
+        if !self.on || !self.range.contains(&pc) {
+            return Ok(());
+        }
+
+        let symbols = self.symbols.then_some(symbols);
+        let line = format_line(cpu, mem, &self.no_symbols, symbols, cycles);
+        writeln!(self.out, "{line}")?;
+        self.num_lines += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.out.flush()?)
+    }
+
+    /// Returns the number of instructions logged.
+    pub fn finish(mut self) -> Result<u64> {
+        self.flush()?;
+        Ok(self.num_lines)
+    }
+}
+
+/// One line of the trace, for the instruction at the PC. With `symbols`, adds
+/// the comment at the end (if there's anything to say).
+fn format_line(
+    cpu: &Cpu,
+    mem: &AddressSpace,
+    no_symbols: &SymbolTable,
+    symbols: Option<&SymbolTable>,
+    cycles: u64,
+) -> String {
+    let pc = cpu.pc();
+    let line = disasm::disassemble(mem, pc, no_symbols);
+    let bytes = line
+        .bytes
+        .iter()
+        .map(|b| match b {
+            Some(b) => format!("{b:02X}"),
+            None => "??".to_string(),
+        })
+        .join(" ");
+    let reg = |r| cpu.register(r);
+    let mut out = format!(
+        "{pc:04X}  {bytes:8}  {:32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{cycles}",
+        line.text,
+        reg(Register::A),
+        reg(Register::X),
+        reg(Register::Y),
+        reg(Register::P),
+        reg(Register::Sp),
+    );
+
+    if let Some(symbols) = symbols {
+        let name = symbols.describe(pc);
+        let source = symbols.line_containing(pc).map(|line| format!("({line})"));
+        let comment = name.into_iter().chain(source).join(" ");
+        if !comment.is_empty() {
+            out += &format!(" ; {comment}");
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::SourceLine;
+
+    #[test]
+    fn line_format() {
+        // LDA #$12
+        // STA $C030
+        let mut mem = AddressSpace::new(&[0xa9, 0x12, 0x8d, 0x30, 0xc0], 0x803);
+        let mut cpu = Cpu::new(0x803);
+        cpu.set_register(Register::P, 0x04).unwrap();
+        cpu.set_register(Register::Sp, 0xfc).unwrap();
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x803, "main");
+        symbols.insert_line(
+            0x803,
+            SourceLine {
+                file: "hello.s".to_string(),
+                line: 3,
+            },
+        );
+
+        let no_symbols = SymbolTable::new();
+        let line = |cpu: &Cpu, mem: &AddressSpace, symbols, cycles| {
+            format_line(cpu, mem, &no_symbols, symbols, cycles)
+        };
+        assert_eq!(
+            line(&cpu, &mem, None, 7),
+            "0803  A9 12     LDA #$12                        A:00 X:00 Y:00 P:04 SP:FC CYC:7",
+        );
+        assert_eq!(
+            line(&cpu, &mem, Some(&symbols), 7),
+            "0803  A9 12     LDA #$12                        A:00 X:00 Y:00 P:04 SP:FC CYC:7 \
+             ; main (hello.s:3)",
+        );
+
+        // (And no symbols in the disassembly itself, even with them on.)
+        cpu.step(&mut mem);
+        assert_eq!(
+            line(&cpu, &mem, Some(&SymbolTable::builtin()), 9),
+            "0805  8D 30 C0  STA $C030                       A:12 X:00 Y:00 P:04 SP:FC CYC:9",
+        );
+    }
+}
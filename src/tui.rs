@@ -0,0 +1,284 @@
+//! Drawing the screen in a terminal, so you can use the emulator over SSH.
+//!
+//! Text is drawn as actual characters. Lo-res is drawn with half-block
+//! characters (two blocks per character cell), and hi-res with braille
+//! characters (2x4 pixels per cell), which needs a 140-column terminal.
+
+use std::{
+    io::{self, prelude::*},
+    ops::ControlFlow,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{self, Attribute, Color, Print, SetAttribute},
+    terminal,
+};
+
+use crate::{
+    display::{self, gr, hgr, text, Frame, Mode, Source},
+    Emulator,
+};
+
+/// Run until the user presses F10.
+pub fn run(emu: Arc<Mutex<Emulator>>) -> Result<()> {
+    let mut stdout = io::stdout();
+    terminal::enable_raw_mode()?;
+    execute!(
+        stdout,
+        terminal::EnterAlternateScreen,
+        cursor::Hide,
+        event::EnableBracketedPaste
+    )?;
+
+    let result = event_loop(&emu);
+
+    execute!(
+        stdout,
+        event::DisableBracketedPaste,
+        cursor::Show,
+        terminal::LeaveAlternateScreen
+    )?;
+    terminal::disable_raw_mode()?;
+    result
+}
+
+fn event_loop(emu: &Mutex<Emulator>) -> Result<()> {
+    let start = Instant::now();
+    let mut prev = vec![];
+
+    loop {
+        // Redraw at ~30 Hz.
+        if event::poll(Duration::from_millis(33))? {
+            match event::read()? {
+                Event::Key(e) if key_event(emu, e).is_break() => return Ok(()),
+                Event::Paste(text) => emu.lock().unwrap().type_text(&text),
+                Event::Resize(..) => prev.clear(),
+                _ => (),
+            }
+        }
+
+        // Flashing text toggles a bit more than twice a second.
+        let flash_on = start.elapsed().as_millis() / 267 % 2 == 1;
+
+        let rows = render(&emu.lock().unwrap(), flash_on);
+        draw(&rows, &mut prev)?;
+    }
+}
+
+fn key_event(emu: &Mutex<Emulator>, e: KeyEvent) -> ControlFlow<()> {
+    if e.kind == KeyEventKind::Release {
+        return ControlFlow::Continue(());
+    }
+
+    // See `Gui::key_event`.
+    let ascii_code = match e.code {
+        KeyCode::F(10) => return ControlFlow::Break(()),
+
+        KeyCode::Char(c)
+            if e.modifiers.contains(KeyModifiers::CONTROL) && c.is_ascii_alphabetic() =>
+        {
+            c.to_ascii_uppercase() as u8 & 0x1f
+        }
+        KeyCode::Char(c) if c.is_ascii() => c as u8,
+        KeyCode::Backspace => 0x7f,
+        KeyCode::Left => 0x08,
+        KeyCode::Tab => 0x09,
+        KeyCode::Down => 0x0a,
+        KeyCode::Up => 0x0b,
+        KeyCode::Enter => 0x0d,
+        KeyCode::Right => 0x15,
+        KeyCode::Esc => 0x1b,
+        _ => return ControlFlow::Continue(()),
+    };
+
+    // Terminals (usually) don't tell us when keys are released, so just
+    // let go right away. (So there's no auto-repeat from the emulated
+    // keyboard, but the terminal does its own.)
+    let mut emu = emu.lock().unwrap();
+    emu.key_down(ascii_code);
+    emu.key_up(ascii_code);
+
+    ControlFlow::Continue(())
+}
+
+type Rgb = [u8; 3];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    c: char,
+    /// `None` means the terminal's default color.
+    fg: Option<Rgb>,
+    bg: Option<Rgb>,
+    reverse: bool,
+}
+
+impl Cell {
+    fn new(c: char) -> Self {
+        Self {
+            c,
+            fg: None,
+            bg: None,
+            reverse: false,
+        }
+    }
+}
+
+/// The screen, as rows of terminal cells. Each row of text becomes one row
+/// of cells, except for hi-res, which needs two.
+fn render(emu: &Emulator, flash_on: bool) -> Vec<Vec<Cell>> {
+    let mode = emu.mem.display_mode();
+    let frame = emu.draw_screen();
+
+    let mut rows = vec![];
+    for y in (0..display::H).step_by(text::CELL_H) {
+        match mode.source(y) {
+            Source::Text => rows.push(text_row(emu, mode, y, flash_on)),
+            Source::Gr => rows.push(half_blocks(&frame, y)),
+            Source::Hgr | Source::DoubleHgr => {
+                rows.push(braille(&frame, y));
+                rows.push(braille(&frame, y + 4));
+            }
+        }
+    }
+    rows
+}
+
+fn text_row(emu: &Emulator, mode: Mode, y: usize, flash_on: bool) -> Vec<Cell> {
+    let line = display::latch(&emu.mem.video_ram(), mode, y);
+
+    text::row_bytes(&line.main, &line.aux, mode.col80)
+        .into_iter()
+        .map(|b| {
+            let (c, attr) = text::decode(b);
+            Cell {
+                reverse: attr == text::Attr::Inverse || (attr == text::Attr::Flash && flash_on),
+                ..Cell::new(c)
+            }
+        })
+        .collect()
+}
+
+/// Each cell shows 2 lo-res blocks: the top one in the foreground color, and
+/// the bottom one in the background color.
+fn half_blocks(frame: &Frame, y: usize) -> Vec<Cell> {
+    let block_w = display::W / gr::W;
+
+    (0..gr::W)
+        .map(|x| {
+            // Sample from the middle of each block, to avoid any fringing at
+            // the edges.
+            let x = x * block_w + block_w / 2;
+            let top = rgb(frame[(y + gr::BLOCK_H / 2) * display::W + x]);
+            let bottom = rgb(frame[(y + gr::BLOCK_H * 3 / 2) * display::W + x]);
+            Cell {
+                fg: Some(top),
+                bg: Some(bottom),
+                ..Cell::new('▀')
+            }
+        })
+        .collect()
+}
+
+/// Each cell shows 2x4 hi-res pixels, in one color. (The average color of
+/// all the pixels that are lit.)
+fn braille(frame: &Frame, y: usize) -> Vec<Cell> {
+    // Which bit of the braille character is which dot.
+    const BITS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+    let dot_w = display::W / hgr::W;
+
+    (0..hgr::W / 2)
+        .map(|x| {
+            let mut bits = 0;
+            let mut sum = [0u32; 3];
+            let mut count = 0;
+
+            for (dx, column) in BITS.iter().enumerate() {
+                for (dy, bit) in column.iter().enumerate() {
+                    let i = (y + dy) * display::W + (2 * x + dx) * dot_w;
+                    // The brighter of the 2 dots that make up this pixel.
+                    let pixel = rgb(frame[i]).max(rgb(frame[i + 1]));
+                    if pixel.iter().all(|&c| c < 0x40) {
+                        continue;
+                    }
+
+                    bits |= bit;
+                    for c in 0..3 {
+                        sum[c] += pixel[c] as u32;
+                    }
+                    count += 1;
+                }
+            }
+
+            if bits == 0 {
+                return Cell::new(' ');
+            }
+            Cell {
+                fg: Some(sum.map(|c| (c / count) as u8)),
+                ..Cell::new(char::from_u32(0x2800 + bits).unwrap())
+            }
+        })
+        .collect()
+}
+
+fn rgb(pixel: u32) -> Rgb {
+    let [_, r, g, b] = pixel.to_be_bytes();
+    [r, g, b]
+}
+
+/// Draw the rows that changed since last time.
+fn draw(rows: &[Vec<Cell>], prev: &mut Vec<Vec<Cell>>) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+
+    // E.g. after switching modes.
+    if rows.len() != prev.len() {
+        queue!(stdout, terminal::Clear(terminal::ClearType::All))?;
+        prev.clear();
+    }
+
+    for (y, row) in rows.iter().enumerate() {
+        if prev.get(y) == Some(row) {
+            continue;
+        }
+
+        queue!(stdout, cursor::MoveTo(0, y as u16))?;
+        // Only change the style when we need to. (Otherwise we'd be sending
+        // a lot of redundant escape codes.)
+        let mut style = None;
+        for cell in row {
+            if style != Some((cell.fg, cell.bg, cell.reverse)) {
+                style = Some((cell.fg, cell.bg, cell.reverse));
+
+                let color = |c: Option<Rgb>| match c {
+                    Some([r, g, b]) => Color::Rgb { r, g, b },
+                    None => Color::Reset,
+                };
+                let reverse = if cell.reverse {
+                    Attribute::Reverse
+                } else {
+                    Attribute::NoReverse
+                };
+                queue!(
+                    stdout,
+                    style::SetColors(style::Colors::new(color(cell.fg), color(cell.bg))),
+                    SetAttribute(reverse),
+                )?;
+            }
+            queue!(stdout, Print(cell.c))?;
+        }
+        queue!(
+            stdout,
+            SetAttribute(Attribute::Reset),
+            terminal::Clear(terminal::ClearType::UntilNewLine)
+        )?;
+    }
+
+    stdout.flush()?;
+    *prev = rows.to_vec();
+    Ok(())
+}
@@ -0,0 +1,337 @@
+//! The video timing generator.
+//!
+//! The beam sweeps across the screen in lock-step with the CPU clock: each
+//! scanline takes 65 cycles, and each frame takes 262 scanlines (192 visible,
+//! and the rest is vertical blanking). We grab each visible line's bytes from
+//! memory as the beam finishes drawing it, so programs that flip soft switches
+//! (or write to the screen) mid-frame look the way they would on real
+//! hardware.
+
+use std::{ops::Range, sync::Arc};
+
+use anyhow::{ensure, Result};
+
+use crate::{
+    display::{self, Frame, Mode, Monitor, VideoRam},
+    save_state::Sections,
+};
+
+pub const CYCLES_PER_LINE: u64 = 65;
+pub const LINES_PER_FRAME: u64 = 262;
+pub const CYCLES_PER_FRAME: u64 = CYCLES_PER_LINE * LINES_PER_FRAME;
+
+#[derive(Clone)]
+pub struct Video {
+    /// Cycles since the start of the current frame.
+    cycle: u64,
+    /// The next line the beam will finish drawing.
+    next_line: usize,
+
+    monitor: Monitor,
+    palette: [u32; 16],
+
+    /// The screen, as drawn so far. This persists across frames, and lines
+    /// only get redrawn if something has changed.
+    pixels: Frame,
+    /// For each line: the mode it was last drawn in, and the write generation
+    /// at that time. (See `DirtyPages`.)
+    drawn: Vec<Option<(Mode, u64)>>,
+    /// How many frames have finished.
+    frames: u64,
+    /// For each line, the number of the frame it was last redrawn in. (The
+    /// frame being drawn is number `frames + 1`.)
+    redrawn: Vec<u64>,
+
+    /// The most recently completed frame. It's shared with the GUI, so
+    /// handing it out doesn't need a copy.
+    frame: Option<Arc<Frame>>,
+}
+
+impl Video {
+    pub fn new() -> Self {
+        let monitor = Monitor::default();
+        Self {
+            cycle: 0,
+            next_line: 0,
+            monitor,
+            palette: monitor.palette(),
+            pixels: vec![0; display::W * display::H],
+            drawn: vec![None; display::H],
+            frames: 0,
+            redrawn: vec![0; display::H],
+            frame: None,
+        }
+    }
+
+    pub fn monitor(&self) -> Monitor {
+        self.monitor
+    }
+
+    pub fn set_monitor(&mut self, monitor: Monitor) {
+        self.monitor = monitor;
+        self.palette = monitor.palette();
+        self.drawn.fill(None);
+    }
+
+    /// Advance the beam. Each visible line gets drawn (if needed) as the beam
+    /// finishes passing over it.
+    ///
+    /// Returns true if the frame just finished.
+    pub fn tick(&mut self, cycles: u8, ram: &VideoRam, mode: Mode, dirty: &DirtyPages) -> bool {
+        self.cycle += cycles as u64;
+
+        while self.next_line < display::H {
+            let y = self.next_line;
+            let end_of_line = (y as u64 + 1) * CYCLES_PER_LINE;
+            if self.cycle < end_of_line {
+                break;
+            }
+            self.next_line += 1;
+
+            let up_to_date = match self.drawn[y] {
+                Some((m, generation)) => {
+                    let range = display::source_range(mode, y);
+                    m == mode && dirty.last_write(range) <= generation
+                }
+                None => false,
+            };
+            if !up_to_date {
+                let line = display::latch(ram, mode, y);
+                display::draw(&mut self.pixels, &line, self.monitor, &self.palette, y);
+                self.drawn[y] = Some((mode, dirty.generation));
+                self.redrawn[y] = self.frames + 1;
+            }
+        }
+
+        if self.cycle >= CYCLES_PER_FRAME {
+            self.cycle -= CYCLES_PER_FRAME;
+            self.next_line = 0;
+
+            // Reuse the buffer, unless someone's still holding onto it.
+            match self.frame.as_mut().and_then(Arc::get_mut) {
+                Some(frame) => frame.copy_from_slice(&self.pixels),
+                None => self.frame = Some(Arc::new(self.pixels.clone())),
+            }
+            self.frames += 1;
+            return true;
+        }
+
+        false
+    }
+
+    pub fn save_state(&self, sections: &mut Sections) {
+        sections.add(b"VID ", self.cycle.to_le_bytes().to_vec());
+    }
+
+    pub fn load_state(&mut self, sections: &Sections) -> Result<()> {
+        let cycle = sections.get(b"VID ")?.u64()?;
+        ensure!(cycle < CYCLES_PER_FRAME, "invalid beam position");
+
+        self.cycle = cycle;
+        self.next_line = (cycle / CYCLES_PER_LINE).min(display::H as u64) as usize;
+        // Anything could have changed.
+        self.drawn.fill(None);
+        Ok(())
+    }
+
+    pub fn frame(&self) -> Option<&Arc<Frame>> {
+        self.frame.as_ref()
+    }
+
+    /// The number of the frame that `frame` returns. They count up from 1.
+    pub fn frame_number(&self) -> u64 {
+        self.frames
+    }
+
+    /// The lines that may differ between frame number `since` and the latest
+    /// one. (Lines outside this range are the same in both.)
+    pub fn lines_redrawn_since(&self, since: u64) -> Range<usize> {
+        // (This can include lines that have only been redrawn in the frame
+        // that's still in progress. That's fine, it's just a bit of extra
+        // work.)
+        let changed = |&y: &usize| self.redrawn[y] > since;
+        let Some(start) = (0..display::H).find(changed) else {
+            return 0..0;
+        };
+        let end = (0..display::H).rfind(changed).unwrap() + 1;
+        start..end
+    }
+
+    pub fn in_vblank(&self) -> bool {
+        self.cycle >= display::H as u64 * CYCLES_PER_LINE
+    }
+
+    /// The address the video hardware is reading from, at the current beam
+    /// position. It keeps reading memory even during blanking, so this is
+    /// always a valid address.
+    ///
+    /// This follows the video scanner equations in chapter 5 of Jim Sather's
+    /// "Understanding the Apple IIe".
+    pub fn scanner_address(&self, mode: Mode) -> u16 {
+        let h = self.cycle % CYCLES_PER_LINE;
+        let v = self.cycle / CYCLES_PER_LINE;
+
+        // The horizontal counter goes $00, $40, $41, ..., $7f. (So it's in the
+        // same state for the first two cycles of each line.) Ignoring the
+        // top bit, that's just `h - 1`.
+        let h = h.saturating_sub(1) as u16;
+        // The vertical counter goes $100, ..., $1ff, then $fa, ..., $ff.
+        let v = if v < 0x100 { 0x100 + v } else { 0xfa + v - 0x100 } as u16;
+
+        let bit = |x: u16, i: u16| x >> i & 1;
+
+        // Lines 160..192 of mixed mode come from the text page.
+        let hires = mode.hires && !(mode.mixed && bit(v, 7) == 1 && bit(v, 5) == 1);
+
+        let addend_1 = bit(h, 5) << 2 | bit(h, 4) << 1 | bit(h, 3);
+        let addend_2 = bit(v, 7) << 3 | bit(v, 6) << 2 | bit(v, 7) << 1 | bit(v, 6);
+        let sum = (0b1101 + addend_1 + addend_2) & 0xf;
+
+        let mut addr = h & 0b111;
+        addr |= sum << 3;
+        addr |= (v >> 3 & 0b111) << 7;
+
+        if hires {
+            addr |= (v & 0b111) << 10;
+            addr | if mode.page2 { 0x4000 } else { 0x2000 }
+        } else {
+            addr | if mode.page2 { 0x800 } else { 0x400 }
+        }
+    }
+}
+
+/// Keeps track of which pages of RAM have been written to, so we know which
+/// lines of the screen need to be redrawn.
+///
+/// Rather than a "dirty" bit, each page stores a generation number: the
+/// value of a counter that goes up on every write. A line needs redrawing
+/// if any of its pages were written after the line was last drawn.
+#[derive(Clone)]
+pub struct DirtyPages {
+    generation: u64,
+    /// Main memory pages, then aux memory pages.
+    last_write: Box<[u64; 2 * 0xc0]>,
+}
+
+impl DirtyPages {
+    pub fn new() -> Self {
+        Self {
+            generation: 0,
+            last_write: Box::new([0; 2 * 0xc0]),
+        }
+    }
+
+    pub fn mark(&mut self, addr: u16, aux: bool) {
+        self.generation += 1;
+        let page = addr as usize >> 8;
+        let idx = if aux { 0xc0 + page } else { page };
+        self.last_write[idx] = self.generation;
+    }
+
+    /// The most recent write to these addresses, in either main or aux
+    /// memory.
+    fn last_write(&self, range: Range<usize>) -> u64 {
+        let pages = range.start >> 8..=(range.end - 1) >> 8;
+        pages
+            .flat_map(|page| [page, 0xc0 + page])
+            .map(|idx| self.last_write[idx])
+            .max()
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::{gr, hgr, text};
+
+    fn mode(hires: bool) -> Mode {
+        Mode {
+            text: !hires,
+            mixed: false,
+            hires,
+            page2: false,
+            col80: false,
+            dhires: false,
+        }
+    }
+
+    /// The first visible cycle of each line.
+    const HBLANK: u64 = CYCLES_PER_LINE - gr::W as u64;
+
+    #[test]
+    fn vblank_timing() {
+        let main = vec![0; 0xc000];
+        let aux = vec![0; 0xc000];
+        let ram = VideoRam {
+            main: &main,
+            aux: &aux,
+        };
+        let dirty = DirtyPages::new();
+
+        let mut video = Video::new();
+        let visible = display::H as u64 * CYCLES_PER_LINE;
+        for _ in 0..2 {
+            for cycle in 0..CYCLES_PER_FRAME {
+                assert_eq!(video.in_vblank(), cycle >= visible, "cycle {cycle}");
+                let end_of_frame = video.tick(1, &ram, mode(false), &dirty);
+                assert_eq!(end_of_frame, cycle == CYCLES_PER_FRAME - 1, "cycle {cycle}");
+            }
+        }
+    }
+
+    #[test]
+    fn lines_redrawn() {
+        let main = vec![0; 0xc000];
+        let aux = vec![0; 0xc000];
+        let ram = VideoRam {
+            main: &main,
+            aux: &aux,
+        };
+        let mut dirty = DirtyPages::new();
+        let mut video = Video::new();
+        let frame = |video: &mut Video, dirty: &DirtyPages| {
+            while !video.tick(1, &ram, mode(true), dirty) {}
+            video.frame_number()
+        };
+
+        let first = frame(&mut video, &dirty);
+        assert_eq!(video.lines_redrawn_since(0), 0..display::H);
+        let second = frame(&mut video, &dirty);
+        assert_eq!(video.lines_redrawn_since(first), 0..0);
+
+        // The page at $2100 only has lines in the middle of the screen.
+        dirty.mark(0x2100, false);
+        frame(&mut video, &dirty);
+        let lines: Vec<_> = (0..display::H)
+            .filter(|&y| hgr::line_offset(y) >> 8 == 1)
+            .collect();
+        let expected = lines[0]..lines[lines.len() - 1] + 1;
+        assert_eq!(video.lines_redrawn_since(second), expected);
+        assert!(expected.start > 0 && expected.end < display::H);
+    }
+
+    #[test]
+    fn scanner_matches_hires_layout() {
+        let mut video = Video::new();
+        for y in 0..display::H {
+            for x in 0..gr::W {
+                video.cycle = y as u64 * CYCLES_PER_LINE + HBLANK + x as u64;
+                let expected = 0x2000 + hgr::line_offset(y) + x;
+                assert_eq!(video.scanner_address(mode(true)), expected as u16);
+            }
+        }
+    }
+
+    #[test]
+    fn scanner_matches_text_layout() {
+        let mut video = Video::new();
+        for y in 0..display::H {
+            for x in 0..gr::W {
+                video.cycle = y as u64 * CYCLES_PER_LINE + HBLANK + x as u64;
+                let expected = 0x400 + gr::row_offset(y / text::CELL_H) + x;
+                assert_eq!(video.scanner_address(mode(false)), expected as u16);
+            }
+        }
+    }
+}